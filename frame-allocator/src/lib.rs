@@ -0,0 +1,485 @@
+#![no_std]
+
+extern crate alloc;
+
+use panda_loader_lib::{MemoryDescriptor, MemoryDescriptorType};
+use x86_64::{
+    structures::paging::{
+        frame::PhysFrameRange, FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const FRAMES_PER_WORD: usize = u64::BITS as usize;
+
+/// Memory that the frame allocator might be given, now or once it's
+/// reclaimed. The bitmap is made big enough to cover all of it.
+const ALLOCATABLE_TYPES: [MemoryDescriptorType; 4] = [
+    MemoryDescriptorType::Available,
+    MemoryDescriptorType::BootServicesReclaimable,
+    MemoryDescriptorType::AcpiReclaimable,
+    MemoryDescriptorType::LoaderReclaimable,
+];
+
+/// Which physical memory an allocation has to come from, for devices that
+/// can't address all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Below 16 MiB, for ISA DMA.
+    Dma,
+    /// Below 4 GiB, for devices with 32-bit DMA addresses.
+    Dma32,
+    /// Anywhere.
+    Normal,
+}
+
+impl Zone {
+    /// The index of the first frame past the zone.
+    fn end(&self) -> usize {
+        match self {
+            Zone::Dma => (0x100_0000 / FRAME_SIZE) as usize,
+            Zone::Dma32 => (0x1_0000_0000 / FRAME_SIZE) as usize,
+            Zone::Normal => usize::MAX,
+        }
+    }
+}
+
+/// How much memory the frame allocator manages, and how much of it is free.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+}
+
+impl FrameStats {
+    pub fn total_bytes(&self) -> u64 {
+        self.total_frames as u64 * FRAME_SIZE
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_frames as u64 * FRAME_SIZE
+    }
+}
+
+/// Hands out 4 KiB frames of physical memory, keeping track of them with one
+/// bit per frame: set if the frame is in use or isn't memory that can be
+/// handed out, clear if it's free.
+///
+/// The bitmap lives in the first available region big enough for it, and
+/// is reached through the direct map of physical memory.
+#[derive(Default)]
+pub struct PhysicalAllocator {
+    bitmap: &'static mut [u64],
+    stats: FrameStats,
+
+    /// No frame in a word before this one is free. Only ever a hint for
+    /// where to start looking.
+    first_free_word: usize,
+}
+
+impl PhysicalAllocator {
+    pub const fn new() -> Self {
+        PhysicalAllocator {
+            bitmap: &mut [],
+            stats: FrameStats {
+                total_frames: 0,
+                free_frames: 0,
+            },
+            first_free_word: 0,
+        }
+    }
+
+    pub fn init(&mut self, descriptors: &[MemoryDescriptor], phys_mem_base: VirtAddr) {
+        let end = descriptors
+            .iter()
+            .filter(|descriptor| ALLOCATABLE_TYPES.contains(&descriptor.memory_type))
+            .map(|descriptor| descriptor.base_addr + descriptor.length)
+            .max()
+            .unwrap_or(PhysAddr::zero());
+
+        let frames = (end.as_u64() / FRAME_SIZE) as usize;
+        let words = frames.div_ceil(FRAMES_PER_WORD);
+        let bitmap_size = (words * core::mem::size_of::<u64>()) as u64;
+
+        let Some(bitmap_start) = descriptors
+            .iter()
+            .filter(|descriptor| descriptor.memory_type == MemoryDescriptorType::Available)
+            .map(|descriptor| {
+                let start = descriptor.base_addr.align_up(FRAME_SIZE);
+                (start, descriptor.base_addr + descriptor.length)
+            })
+            .find(|(start, end)| *start + bitmap_size <= *end)
+            .map(|(start, _)| start)
+        else {
+            log::error!("No room for a {bitmap_size} byte frame bitmap, so no frames to allocate");
+            return;
+        };
+
+        // everything starts out in use, then the available memory is freed
+        self.bitmap = unsafe {
+            let bitmap = (phys_mem_base + bitmap_start.as_u64()).as_mut_ptr::<u64>();
+            core::slice::from_raw_parts_mut(bitmap, words)
+        };
+        self.bitmap.fill(u64::MAX);
+
+        self.add_regions(descriptors, MemoryDescriptorType::Available);
+
+        let bitmap_frames = bitmap_size.div_ceil(FRAME_SIZE);
+        let bitmap_start = PhysFrame::containing_address(bitmap_start);
+        self.mark_used(PhysFrame::range(bitmap_start, bitmap_start + bitmap_frames));
+    }
+
+    /// Starts handing out frames from every region of the given type.
+    /// Returns the number of bytes added.
+    pub fn add_regions(
+        &mut self,
+        descriptors: &[MemoryDescriptor],
+        memory_type: MemoryDescriptorType,
+    ) -> u64 {
+        let mut added = 0;
+
+        for descriptor in descriptors
+            .iter()
+            .filter(|descriptor| descriptor.memory_type == memory_type)
+        {
+            let start = PhysFrame::containing_address(descriptor.base_addr.align_up(FRAME_SIZE));
+            let end = PhysFrame::containing_address(descriptor.base_addr + descriptor.length);
+
+            for frame in PhysFrame::range(start, end) {
+                let index = Self::index(frame);
+
+                if index >= self.frame_count() {
+                    log::warn!("Memory region outside the frame bitmap, ignoring {descriptor:?}");
+                    break;
+                }
+
+                if self.is_used(index) {
+                    self.set_free(index);
+                    self.stats.total_frames += 1;
+                    self.stats.free_frames += 1;
+                    self.first_free_word = self.first_free_word.min(index / FRAMES_PER_WORD);
+                    added += FRAME_SIZE;
+                }
+            }
+        }
+
+        added
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Finds `count` free frames in a row, all inside `zone`.
+    pub fn allocate_frames(&mut self, count: usize, zone: Zone) -> Option<PhysFrameRange> {
+        if count == 0 {
+            return None;
+        }
+
+        let end = zone.end().min(self.frame_count());
+
+        // keep the low memory that only some devices can use for them, if
+        // there's anywhere else to go
+        let start = match zone {
+            Zone::Normal => self.first_free_word * FRAMES_PER_WORD,
+            _ => 0,
+        };
+
+        let first = self
+            .find_free(start.max(Zone::Dma.end()), end, count)
+            .or_else(|| self.find_free(start, end, count))?;
+
+        let first = Self::frame(first);
+        let range = PhysFrame::range(first, first + count as u64);
+        self.mark_used(range);
+
+        Some(range)
+    }
+
+    /// Gives back frames from `allocate_frames`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may use the frames afterwards.
+    pub unsafe fn deallocate_frames(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            let index = Self::index(frame);
+
+            if index >= self.frame_count() || !self.is_used(index) {
+                log::warn!("Freeing a frame that isn't allocated: {frame:?}");
+                continue;
+            }
+
+            self.set_free(index);
+            self.stats.free_frames += 1;
+            self.first_free_word = self.first_free_word.min(index / FRAMES_PER_WORD);
+        }
+    }
+
+    /// The first of `count` free frames in a row between `start` and `end`.
+    fn find_free(&self, start: usize, end: usize, count: usize) -> Option<usize> {
+        let mut index = start;
+        let mut run = 0;
+
+        while index < end {
+            // skip over words with nothing free in them
+            if index.is_multiple_of(FRAMES_PER_WORD)
+                && self.bitmap[index / FRAMES_PER_WORD] == u64::MAX
+            {
+                run = 0;
+                index += FRAMES_PER_WORD;
+                continue;
+            }
+
+            if self.is_used(index) {
+                run = 0;
+            } else {
+                run += 1;
+
+                if run == count {
+                    return Some(index + 1 - count);
+                }
+            }
+
+            index += 1;
+        }
+
+        None
+    }
+
+    fn mark_used(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            let index = Self::index(frame);
+
+            if index < self.frame_count() && !self.is_used(index) {
+                self.set_used(index);
+                self.stats.free_frames -= 1;
+            }
+        }
+
+        // only moves on if the word it pointed at filled up
+        while self.first_free_word < self.bitmap.len()
+            && self.bitmap[self.first_free_word] == u64::MAX
+        {
+            self.first_free_word += 1;
+        }
+    }
+
+    fn frame_count(&self) -> usize {
+        self.bitmap.len() * FRAMES_PER_WORD
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / FRAMES_PER_WORD] & (1 << (index % FRAMES_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, index: usize) {
+        self.bitmap[index / FRAMES_PER_WORD] |= 1 << (index % FRAMES_PER_WORD);
+    }
+
+    fn set_free(&mut self, index: usize) {
+        self.bitmap[index / FRAMES_PER_WORD] &= !(1 << (index % FRAMES_PER_WORD));
+    }
+
+    fn index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+
+    fn frame(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for PhysicalAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate_frames(1, Zone::Normal)
+            .map(|range| range.start)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for PhysicalAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.deallocate_frames(PhysFrame::range(frame, frame + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn descriptor(start: u64, end: u64, memory_type: MemoryDescriptorType) -> MemoryDescriptor {
+        MemoryDescriptor {
+            base_addr: PhysAddr::new(start),
+            length: end - start,
+            memory_type,
+        }
+    }
+
+    /// An allocator over the given memory map, with its bitmap in a buffer
+    /// on the heap standing in for the first available region.
+    fn allocator(descriptors: &[MemoryDescriptor]) -> PhysicalAllocator {
+        let end = descriptors
+            .iter()
+            .map(|descriptor| (descriptor.base_addr + descriptor.length).as_u64())
+            .max()
+            .unwrap();
+        let bitmap_start = descriptors
+            .iter()
+            .find(|descriptor| descriptor.memory_type == MemoryDescriptorType::Available)
+            .unwrap()
+            .base_addr;
+
+        let words = (end / FRAME_SIZE) as usize / FRAMES_PER_WORD + 1;
+        let bitmap = vec![0u64; words].leak();
+        let phys_mem_base = VirtAddr::new(bitmap.as_ptr() as u64 - bitmap_start.as_u64());
+
+        let mut allocator = PhysicalAllocator::new();
+        allocator.init(descriptors, phys_mem_base);
+        allocator
+    }
+
+    fn start(range: PhysFrameRange) -> u64 {
+        range.start.start_address().as_u64()
+    }
+
+    #[test]
+    fn test_init_reserves_bitmap() {
+        let allocator = allocator(&[
+            descriptor(0, MIB, MemoryDescriptorType::Reserved),
+            descriptor(MIB, 2 * MIB, MemoryDescriptorType::Available),
+        ]);
+
+        // the bitmap fits in the first frame of the available region
+        let stats = allocator.stats();
+        assert_eq!(stats.total_frames, 256);
+        assert_eq!(stats.free_frames, 255);
+        assert_eq!(stats.total_bytes(), MIB);
+    }
+
+    #[test]
+    fn test_alloc_free_round_trip() {
+        let mut allocator = allocator(&[descriptor(
+            32 * MIB,
+            33 * MIB,
+            MemoryDescriptorType::Available,
+        )]);
+        let free = allocator.stats().free_frames;
+
+        let frames = allocator.allocate_frames(4, Zone::Normal).unwrap();
+        assert_eq!(start(frames), 32 * MIB + FRAME_SIZE);
+        assert_eq!(allocator.stats().free_frames, free - 4);
+
+        unsafe { allocator.deallocate_frames(frames) };
+        assert_eq!(allocator.stats().free_frames, free);
+
+        // freeing twice doesn't count the frames twice
+        unsafe { allocator.deallocate_frames(frames) };
+        assert_eq!(allocator.stats().free_frames, free);
+
+        assert_eq!(allocator.allocate_frames(4, Zone::Normal), Some(frames));
+    }
+
+    #[test]
+    fn test_allocate_until_empty() {
+        let mut allocator = allocator(&[descriptor(
+            32 * MIB,
+            32 * MIB + 8 * FRAME_SIZE,
+            MemoryDescriptorType::Available,
+        )]);
+
+        assert_eq!(allocator.allocate_frames(0, Zone::Normal), None);
+        assert_eq!(allocator.allocate_frames(8, Zone::Normal), None);
+
+        for _ in 0..7 {
+            assert!(allocator.allocate_frame().is_some());
+        }
+
+        assert_eq!(allocator.allocate_frame(), None);
+        assert_eq!(allocator.stats().free_frames, 0);
+    }
+
+    #[test]
+    fn test_find_free_skips_short_runs() {
+        // three free frames, a used one, then eight more
+        let mut allocator = allocator(&[
+            descriptor(
+                32 * MIB,
+                32 * MIB + 3 * FRAME_SIZE,
+                MemoryDescriptorType::Available,
+            ),
+            descriptor(
+                32 * MIB + 4 * FRAME_SIZE,
+                32 * MIB + 12 * FRAME_SIZE,
+                MemoryDescriptorType::Available,
+            ),
+        ]);
+
+        let frames = allocator.allocate_frames(5, Zone::Normal).unwrap();
+        assert_eq!(start(frames), 32 * MIB + 4 * FRAME_SIZE);
+
+        // the bitmap took the first frame, which leaves two before the gap
+        let frames = allocator.allocate_frames(2, Zone::Normal).unwrap();
+        assert_eq!(start(frames), 32 * MIB + FRAME_SIZE);
+
+        assert_eq!(allocator.allocate_frames(4, Zone::Normal), None);
+    }
+
+    #[test]
+    fn test_zone_limits() {
+        let mut allocator = allocator(&[
+            descriptor(MIB, 2 * MIB, MemoryDescriptorType::Available),
+            descriptor(32 * MIB, 33 * MIB, MemoryDescriptorType::Available),
+            descriptor(4096 * MIB, 4097 * MIB, MemoryDescriptorType::Available),
+        ]);
+
+        // low memory is kept for the devices that need it
+        let frame = allocator.allocate_frame().unwrap();
+        assert!(frame.start_address().as_u64() >= 16 * MIB);
+
+        let frames = allocator.allocate_frames(16, Zone::Dma).unwrap();
+        assert!(frames.end.start_address().as_u64() <= 16 * MIB);
+
+        let frames = allocator.allocate_frames(128, Zone::Dma32).unwrap();
+        assert_eq!(start(frames), 32 * MIB + FRAME_SIZE);
+
+        // the only run that's long enough is above 4 GiB
+        assert_eq!(allocator.allocate_frames(256, Zone::Dma32), None);
+        let frames = allocator.allocate_frames(256, Zone::Normal).unwrap();
+        assert_eq!(start(frames), 4096 * MIB);
+    }
+
+    #[test]
+    fn test_add_regions() {
+        // not page aligned, so only the whole frames inside it count
+        let reclaimable = [descriptor(
+            34 * MIB + 0x800,
+            34 * MIB + 3 * FRAME_SIZE + 0x800,
+            MemoryDescriptorType::BootServicesReclaimable,
+        )];
+
+        let mut allocator = allocator(&[
+            descriptor(32 * MIB, 33 * MIB, MemoryDescriptorType::Available),
+            reclaimable[0],
+        ]);
+        let stats = allocator.stats();
+
+        let added = allocator.add_regions(&reclaimable, MemoryDescriptorType::Available);
+        assert_eq!(added, 0);
+
+        let added =
+            allocator.add_regions(&reclaimable, MemoryDescriptorType::BootServicesReclaimable);
+        assert_eq!(added, 2 * FRAME_SIZE);
+        assert_eq!(allocator.stats().total_frames, stats.total_frames + 2);
+        assert_eq!(allocator.stats().free_frames, stats.free_frames + 2);
+
+        // adding the same memory again changes nothing
+        let added =
+            allocator.add_regions(&reclaimable, MemoryDescriptorType::BootServicesReclaimable);
+        assert_eq!(added, 0);
+        assert_eq!(allocator.stats().total_frames, stats.total_frames + 2);
+    }
+}
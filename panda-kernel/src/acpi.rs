@@ -58,8 +58,6 @@ pub fn init<'a>(
         None => return Err(AcpiError::NoRsdpProvided),
     };
 
-    let rsdp_address = memory::physical_to_virtual(rsdp_address);
-
     let acpi_tables =
         unsafe { AcpiTables::from_rsdp(AcpiMemoryHandler, rsdp_address.as_u64() as usize)? };
 
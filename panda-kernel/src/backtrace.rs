@@ -0,0 +1,105 @@
+//! Backtraces for panics and fatal exceptions, found by following the chain
+//! of saved frame pointers (the kernel is always built with them) and named
+//! using the symbol table the loader handed over.
+
+use core::arch::asm;
+
+use panda_loader_lib::SymbolTable;
+use spin::Once;
+use x86_64::VirtAddr;
+
+use crate::memory;
+
+const MAX_FRAMES: usize = 64;
+
+// the symbol table has link-time addresses, so also keep how far the loader
+// moved the kernel from there
+static SYMBOLS: Once<(SymbolTable<'static>, u64)> = Once::new();
+
+/// The symbol table has to be somewhere that's never reclaimed.
+pub fn init(symbols: SymbolTable<'static>, kernel_slide: u64) {
+    log::debug!(
+        "{} kernel symbols available for backtraces, kernel slide is {kernel_slide:#x}",
+        symbols.len()
+    );
+    SYMBOLS.call_once(|| (symbols, kernel_slide));
+}
+
+/// Prints a backtrace of whoever called this.
+#[inline(never)]
+pub fn print_backtrace() {
+    let frame_pointer: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack));
+    }
+
+    println!("Backtrace:");
+    print_frames(0, frame_pointer);
+}
+
+/// Prints a backtrace of the code an exception interrupted. Must be called
+/// directly from the exception handler, so that the handler's saved frame
+/// pointer is the interrupted code's.
+#[inline(always)]
+pub fn print_exception_backtrace(instruction_pointer: VirtAddr) {
+    let frame_pointer: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack));
+    }
+
+    println!("Backtrace:");
+    print_frame(0, instruction_pointer.as_u64());
+
+    // the handler's frame points back to the interrupted code's, but there's
+    // an interrupt stack frame rather than a return address above it
+    if let Some(interrupted_frame_pointer) = read_stack(frame_pointer) {
+        print_frames(1, interrupted_frame_pointer);
+    }
+}
+
+fn print_frames(first_index: usize, mut frame_pointer: u64) {
+    for index in first_index..first_index + MAX_FRAMES {
+        let (Some(next_frame_pointer), Some(return_address)) =
+            (read_stack(frame_pointer), read_stack(frame_pointer + 8))
+        else {
+            break;
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        print_frame(index, return_address);
+
+        // stacks grow down, so anything else means the chain is broken
+        if next_frame_pointer <= frame_pointer {
+            break;
+        }
+
+        frame_pointer = next_frame_pointer;
+    }
+}
+
+fn print_frame(index: usize, address: u64) {
+    let symbol = SYMBOLS
+        .get()
+        .and_then(|(symbols, slide)| symbols.lookup(address.wrapping_sub(*slide)));
+
+    match symbol {
+        Some((name, offset)) => println!("  {index:2}: {address:#018x} {name}+{offset:#x}"),
+        None => println!("  {index:2}: {address:#018x} <unknown>"),
+    }
+}
+
+/// Reads a word off the stack, if it's somewhere that can be read without
+/// faulting again.
+fn read_stack(address: u64) -> Option<u64> {
+    if address == 0 || address % 8 != 0 {
+        return None;
+    }
+
+    let address = VirtAddr::try_new(address).ok()?;
+    memory::virtual_to_physical(address)?;
+
+    Some(unsafe { address.as_ptr::<u64>().read() })
+}
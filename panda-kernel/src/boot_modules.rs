@@ -0,0 +1,38 @@
+//! Files the loader brought into memory alongside the kernel, as listed by
+//! `module = ...` lines in `panda.cfg`.
+
+use alloc::vec::Vec;
+use panda_loader_lib::BootModule;
+use spin::Once;
+
+use crate::memory;
+
+static BOOT_MODULES: Once<Vec<BootModule>> = Once::new();
+
+/// Must be called after the heap is set up, since the list is copied out of
+/// the care package.
+pub fn init(modules: &[BootModule]) {
+    for module in modules {
+        log::info!(
+            "Boot module {}: {} bytes at {:?}",
+            module.name(),
+            module.size,
+            module.base_addr
+        );
+    }
+
+    BOOT_MODULES.call_once(|| modules.to_vec());
+}
+
+pub fn modules() -> &'static [BootModule] {
+    BOOT_MODULES.get().map(Vec::as_slice).unwrap_or(&[])
+}
+
+/// The contents of the module with the given name.
+#[allow(dead_code)]
+pub fn find(name: &str) -> Option<&'static [u8]> {
+    let module = modules().iter().find(|module| module.name() == name)?;
+    let addr = memory::physical_to_virtual(module.base_addr);
+
+    Some(unsafe { core::slice::from_raw_parts(addr.as_ptr(), module.size as usize) })
+}
@@ -0,0 +1,104 @@
+//! The kernel command line, e.g. `log=debug nodisplay drivers.disable=pc_keyboard`.
+//!
+//! Options are separated by whitespace and are either `key=value` pairs or
+//! bare flags. Values can be put in double quotes to include spaces. If a key
+//! is given more than once, the last one wins.
+
+use spin::Once;
+
+const MAX_COMMAND_LINE_LENGTH: usize = 4096;
+
+// the loader's copy may not stay around, so keep our own
+static mut COMMAND_LINE_BUFFER: [u8; MAX_COMMAND_LINE_LENGTH] = [0; MAX_COMMAND_LINE_LENGTH];
+static COMMAND_LINE: Once<&'static str> = Once::new();
+
+pub fn init(command_line: &str) {
+    let mut length = command_line.len().min(MAX_COMMAND_LINE_LENGTH);
+    while !command_line.is_char_boundary(length) {
+        length -= 1;
+    }
+
+    if length < command_line.len() {
+        println!("Command line is longer than {MAX_COMMAND_LINE_LENGTH} bytes, truncating it");
+    }
+
+    COMMAND_LINE.call_once(|| unsafe {
+        COMMAND_LINE_BUFFER[..length].copy_from_slice(&command_line.as_bytes()[..length]);
+        core::str::from_utf8_unchecked(&COMMAND_LINE_BUFFER[..length])
+    });
+}
+
+pub fn command_line() -> &'static str {
+    COMMAND_LINE.get().copied().unwrap_or("")
+}
+
+/// Every option on the command line, as `(key, value)` pairs. Flags have no value.
+pub fn options() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    Tokens(command_line()).map(|token| match token.split_once('=') {
+        Some((key, value)) => (key, Some(unquote(value))),
+        None => (token, None),
+    })
+}
+
+/// The value of a `key=value` option.
+pub fn get(key: &str) -> Option<&'static str> {
+    options()
+        .filter(|(option, _)| *option == key)
+        .last()
+        .and_then(|(_, value)| value)
+}
+
+/// Whether a flag is turned on, either by being given on its own or with a
+/// value like `key=on`.
+pub fn flag(key: &str) -> bool {
+    match options().filter(|(option, _)| *option == key).last() {
+        None => false,
+        Some((_, None)) => true,
+        Some((_, Some(value))) => matches!(value, "1" | "on" | "yes" | "true"),
+    }
+}
+
+/// The comma-separated values of an option, like `drivers.disable=a,b`.
+pub fn list(key: &str) -> impl Iterator<Item = &'static str> {
+    get(key)
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Splits the command line on whitespace that isn't inside double quotes.
+struct Tokens(&'static str);
+
+impl Iterator for Tokens {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.0.trim_start();
+        if remaining.is_empty() {
+            return None;
+        }
+
+        let mut in_quotes = false;
+        let end = remaining
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .map(|(index, _)| index)
+            .unwrap_or(remaining.len());
+
+        self.0 = &remaining[end..];
+        Some(&remaining[..end])
+    }
+}
@@ -1,9 +1,14 @@
 use core::fmt::Write;
 
+use panda_loader_lib::PANIC_REPORT_MAX_SIZE;
+use spin::Mutex;
 use uart_16550::SerialPort;
 
 static mut QEMU_OUTPUT: Option<SerialPort> = None;
 
+/// The end of the console output, for the panic report.
+static LOG_TAIL: Mutex<LogTail> = Mutex::new(LogTail::new());
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
@@ -20,6 +25,13 @@ pub fn _print(args: core::fmt::Arguments) {
     if let Some(serial_port) = unsafe { &mut QEMU_OUTPUT } {
         let _ = serial_port.write_fmt(args);
     }
+
+    // don't wait for the lock, since whoever has it might be what this
+    // interrupted or panicked out of
+    if let Some(mut log_tail) = LOG_TAIL.try_lock() {
+        let _ = log_tail.write_fmt(args);
+    }
+
     // display::write_text(TextPart(
     //     alloc::format!("{}", args).as_str(),
     //     FontSize::Regular,
@@ -34,3 +46,56 @@ pub fn init() {
         QEMU_OUTPUT = Some(serial_port);
     }
 }
+
+/// Copies as much of the end of the console output as fits into `buffer`,
+/// returning how many bytes were copied. Doesn't wait for the lock, so it's
+/// safe to call while panicking.
+pub fn copy_log_tail(buffer: &mut [u8]) -> usize {
+    let Some(log_tail) = LOG_TAIL.try_lock() else {
+        return 0;
+    };
+
+    let (newest, oldest) = log_tail.buffer.split_at(log_tail.next);
+    let oldest = if log_tail.full { oldest } else { &[] };
+
+    let length = (oldest.len() + newest.len()).min(buffer.len());
+    let skipped = oldest.len() + newest.len() - length;
+
+    for (slot, byte) in buffer
+        .iter_mut()
+        .zip(oldest.iter().chain(newest).skip(skipped))
+    {
+        *slot = *byte;
+    }
+
+    length
+}
+
+/// A ring buffer of the last bytes written to the console.
+struct LogTail {
+    buffer: [u8; PANIC_REPORT_MAX_SIZE],
+    next: usize,
+    full: bool,
+}
+
+impl LogTail {
+    const fn new() -> Self {
+        LogTail {
+            buffer: [0; PANIC_REPORT_MAX_SIZE],
+            next: 0,
+            full: false,
+        }
+    }
+}
+
+impl Write for LogTail {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buffer[self.next] = byte;
+            self.next = (self.next + 1) % self.buffer.len();
+            self.full |= self.next == 0;
+        }
+
+        Ok(())
+    }
+}
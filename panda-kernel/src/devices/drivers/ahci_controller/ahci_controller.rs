@@ -1,7 +1,7 @@
 use x86_64::{PhysAddr, VirtAddr};
 
 use crate::{
-    memory,
+    memory::vma::{ioremap, CacheType},
     pci::{PciDevice, PciRegister},
 };
 
@@ -22,14 +22,12 @@ pub struct AhciController {
 impl AhciController {
     pub fn new(pci_device: PciDevice) -> Self {
         let ahci_base_phys = pci_device.read::<u32>(PciRegister::BaseAddress5) as u64;
-        let ahci_base = memory::physical_to_virtual(PhysAddr::new(ahci_base_phys));
 
-        let ahci_end_phys = ahci_base_phys + 0x1100;
-        let ahci_end = memory::physical_to_virtual(PhysAddr::new(ahci_end_phys));
-
-        unsafe {
-            memory::mark_as_uncacheable(ahci_base, ahci_end);
-        }
+        // the generic host control registers and all 32 ports' registers
+        let ahci_base = unsafe {
+            ioremap(PhysAddr::new(ahci_base_phys), 0x1100, CacheType::Uncached)
+                .expect("Failed to map AHCI registers")
+        };
         log::info!("    -> Device detection init");
 
         Self {
@@ -1,8 +1,9 @@
 use bitfield::bitfield;
 
+// The command list is 32 of these back to back, so only the list as a whole
+// is 1K aligned.
 bitfield! {
     #[derive(Default, Clone, Copy)]
-    #[repr(align(1024))]
     pub struct AhciCommandHeader(/* MSB0 */ [u32]);
 
     // DW 0
@@ -20,7 +20,7 @@ use crate::{
         ahci_wait_for_port_interrupt,
         registers::{AhciCommandAndStatusRegister, AhciPortSataStatusRegister},
     },
-    memory,
+    memory::{dma::DmaBuffer, Zone},
 };
 
 use self::{
@@ -31,43 +31,33 @@ use self::{
     register::AhciPortRegister,
 };
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::vec::Vec;
 use ata::ATACommand;
 use thingbuf::mpsc::Receiver;
-use x86_64::VirtAddr;
 
 pub type AhciCommandListStructure = [AhciCommandHeader<[u32; 8]>; 32];
 
-pub async fn ahci_port_task(mut port: AhciPort, channel: Receiver<AhciPortCommand>) {
+/// `dma_zone` is the memory the controller can address, which is only the
+/// first 4 GiB unless it supports 64-bit addressing.
+pub async fn ahci_port_task(
+    mut port: AhciPort,
+    channel: Receiver<AhciPortCommand>,
+    dma_zone: Zone,
+) {
     // Allocate physical memory for its command list, the received FIS, and its command tables.
-    // Make sure the command tables are 128 byte aligned.
-    let command_list_stucture: AhciCommandListStructure = Default::default();
-    let mut command_list_structure = Box::new(command_list_stucture);
-
-    let mut command_tables: Box<[AhciCommandTable; 32]> = Box::new(Default::default());
-    let received_fis = Box::new(AhciFis::default());
-
-    // Memory map these slots as uncacheable.
-    unsafe {
-        memory::mark_deref_as_uncacheable(command_list_structure.as_ptr());
-        memory::mark_deref_as_uncacheable(command_tables.as_ptr());
-        memory::mark_deref_as_uncacheable(&*received_fis as *const AhciFis);
-    };
+    // DMA buffers start on a page boundary, which is more than the 1K, 256 byte and 128 byte
+    // alignment these need.
+    let mut command_list_structure = DmaBuffer::new(AhciCommandListStructure::default(), dma_zone)
+        .expect("Failed to allocate command list");
+    let mut command_tables: DmaBuffer<[AhciCommandTable; 32]> =
+        DmaBuffer::new(Default::default(), dma_zone).expect("Failed to allocate command tables");
+    let received_fis =
+        DmaBuffer::new(AhciFis::default(), dma_zone).expect("Failed to allocate received FIS");
 
     // Set command list and received FIS address registers (and upper registers, if supported).
-    let command_list_structure_addr = command_list_structure.as_ptr() as usize as u64;
-    let command_list_structure_addr =
-        memory::virtual_to_physical(VirtAddr::new(command_list_structure_addr))
-            .expect("CLB base address not mapped")
-            .as_u64();
+    let command_list_structure_addr = command_list_structure.physical_address().as_u64();
     log::info!("Command list structure address: {command_list_structure_addr:016X?}");
 
-    assert_eq!(
-        command_list_structure_addr & 0b1111111111,
-        0,
-        "CLB must be 1K aligned"
-    );
-
     port.write(
         AhciPortRegister::CommandListBaseAddress,
         (command_list_structure_addr >> 0) as u32,
@@ -77,15 +67,7 @@ pub async fn ahci_port_task(mut port: AhciPort, channel: Receiver<AhciPortComman
         (command_list_structure_addr >> 32) as u32,
     );
 
-    let received_fis_addr = &*received_fis as *const AhciFis as usize as u64;
-    let received_fis_addr = memory::virtual_to_physical(VirtAddr::new(received_fis_addr))
-        .expect("Received FIS base address not mapped")
-        .as_u64();
-    assert_eq!(
-        received_fis_addr & 0b11111111,
-        0,
-        "FIS base address must be 256-byte aligned"
-    );
+    let received_fis_addr = received_fis.physical_address().as_u64();
 
     log::info!(
         "Port {} Received FIS address: {received_fis_addr:X}",
@@ -150,11 +132,9 @@ pub async fn ahci_port_task(mut port: AhciPort, channel: Receiver<AhciPortComman
             AhciPortCommand::Identify(reply) => {
                 log::info!("Received IDENTIFY command");
 
-                let result_data = [0u8; 512];
-                let result_data_addr =
-                    memory::virtual_to_physical(VirtAddr::new(result_data.as_ptr() as u64))
-                        .expect("could not translate result data to physical")
-                        .as_u64();
+                let result_data = DmaBuffer::new([0u8; 512], dma_zone)
+                    .expect("Failed to allocate IDENTIFY buffer");
+                let result_data_addr = result_data.physical_address().as_u64();
 
                 let mut phys_region = AhciPhysicalRegionDescriptor([0u32; 4]);
                 phys_region.set_data_base_addr_upper((result_data_addr >> 32) as u32);
@@ -177,7 +157,7 @@ pub async fn ahci_port_task(mut port: AhciPort, channel: Receiver<AhciPortComman
                 .await;
 
                 let ide_identify =
-                    unsafe { core::mem::transmute::<[u8; 512], IdeIdentifyData>(result_data) };
+                    unsafe { core::mem::transmute::<[u8; 512], IdeIdentifyData>(*result_data) };
                 assert_eq!(ide_identify.signature, 0x0040);
 
                 reply
@@ -187,16 +167,14 @@ pub async fn ahci_port_task(mut port: AhciPort, channel: Receiver<AhciPortComman
             }
 
             AhciPortCommand::Read(command, reply) => {
-                let mut data = Vec::new();
-                data.resize_with(command.sector_count as usize, || [0x55u8; 512]);
+                let data =
+                    DmaBuffer::from_elem([0x55u8; 512], command.sector_count as usize, dma_zone)
+                        .expect("Failed to allocate READ buffer");
 
                 let phys_regions = data
                     .iter()
                     .map(|v| {
-                        let phys_addr =
-                            memory::virtual_to_physical(VirtAddr::new(v.as_ptr() as u64))
-                                .expect("could not translate result data to physical")
-                                .as_u64();
+                        let phys_addr = data.physical_address_of(v).as_u64();
 
                         let mut phys_region = AhciPhysicalRegionDescriptor([0u32; 4]);
                         phys_region.set_data_base_addr_upper((phys_addr >> 32) as u32);
@@ -224,7 +202,9 @@ pub async fn ahci_port_task(mut port: AhciPort, channel: Receiver<AhciPortComman
                 .await;
 
                 reply
-                    .send(ReadCommandReply { data })
+                    .send(ReadCommandReply {
+                        data: data.to_vec(),
+                    })
                     .await
                     .expect("Failed to send reply")
             }
@@ -255,7 +235,7 @@ fn find_free_slot(port: &AhciPort, command_list: &[AhciCommandTable; 32]) -> Opt
 
 async fn perform_ata_command(
     port: &mut AhciPort,
-    command_tables: &mut [AhciCommandTable; 32],
+    command_tables: &mut DmaBuffer<[AhciCommandTable; 32]>,
     command_list_structure: &mut AhciCommandListStructure,
     command_fis: &[u8; 64],
     phys_regions: &[AhciPhysicalRegionDescriptor<[u32; 4]>],
@@ -265,11 +245,10 @@ async fn perform_ata_command(
     };
     log::info!("Free slot: {free_slot}");
 
-    let command_table = &mut command_tables[free_slot];
-    let command_table_addr = command_table as *mut AhciCommandTable as u64;
-    let command_table_addr = memory::virtual_to_physical(VirtAddr::new(command_table_addr))
-        .expect("could not translate command tale address to physical")
+    let command_table_addr = command_tables
+        .physical_address_of(&command_tables[free_slot])
         .as_u64();
+    let command_table = &mut command_tables[free_slot];
 
     let command_header = &mut command_list_structure[free_slot];
     command_header
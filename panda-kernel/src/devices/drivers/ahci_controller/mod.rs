@@ -19,7 +19,7 @@ use crate::{
         registers::AhciPortInterruptStatusRegister,
     },
     irq::{configure_irq, enable_irq, end_of_interrupt},
-    memory,
+    memory::Zone,
     pci::{PciDevice, PciRegister},
     task,
     util::async_ring_queue::AsyncRingQueue,
@@ -70,12 +70,6 @@ async fn ahci_controller_task(mut controller: AhciController) {
         .write(PciRegister::Command, command.0);
     log::info!("  -> Configured PCI device");
 
-    // Memory map BAR 5 register as uncacheable.
-    let bar5 = controller.ahci_base_addr();
-    unsafe {
-        memory::mark_as_uncacheable(bar5, bar5);
-    }
-
     controller.perform_bios_os_handoff();
     controller.hba_reset();
 
@@ -88,8 +82,13 @@ async fn ahci_controller_task(mut controller: AhciController) {
     // Enable AHCI mode and interrupts in global host control register.
     controller.enable_ahci_and_interrupts();
 
-    // Read capabilities registers. Check 64-bit DMA is supported if you need it.
+    // Read capabilities registers. Without 64-bit addressing, DMA buffers have to be below 4 GiB.
     let capabilities = controller.capabilities();
+    let dma_zone = if capabilities.supports_64bit_addressing() {
+        Zone::Normal
+    } else {
+        Zone::Dma32
+    };
 
     log::info!("AHCI controller initialized, configuring ports...");
 
@@ -98,7 +97,7 @@ async fn ahci_controller_task(mut controller: AhciController) {
     for index in 0..=capabilities.number_of_ports() {
         if let Some(port) = controller.port(index) {
             let (sender, receiver) = channel(10);
-            task::start(ahci_port_task(port, receiver));
+            task::start(ahci_port_task(port, receiver, dma_zone));
             ports.push((index, sender));
         }
     }
@@ -1,7 +1,7 @@
 use alloc::string::String;
 use aml::NamespaceLevel;
 
-use crate::{acpi::EnumerateAcpiDeviceBehaviour, pci::{PciDevice, PciRegister}};
+use crate::{acpi::EnumerateAcpiDeviceBehaviour, cmdline, pci::{PciDevice, PciRegister}};
 
 pub mod pc_keyboard;
 pub mod pci_host_bridge;
@@ -11,12 +11,12 @@ pub fn init_by_acpi(hid: String, level: NamespaceLevel) -> EnumerateAcpiDeviceBe
     let mut behaviour = EnumerateAcpiDeviceBehaviour::TraverseChildren;
 
     match hid.as_str() {
-        "PNP0A03" | "PNP0A08" => {
+        "PNP0A03" | "PNP0A08" if is_enabled("pci_host_bridge") => {
             pci_host_bridge::init_from_acpi_level(level);
             behaviour = EnumerateAcpiDeviceBehaviour::NoTraverseChildren;
         }
 
-        "PNP0303" => pc_keyboard::init_from_acpi_level(level),
+        "PNP0303" if is_enabled("pc_keyboard") => pc_keyboard::init_from_acpi_level(level),
         _ => {}
     }
 
@@ -37,10 +37,24 @@ pub fn init_by_pci(pci_device: PciDevice) {
         //     isa_host_bridge::init_from_pci_device(pci_device)
         // }
 
-        (0x01, 0x06, 0x01, _, _, _) => ahci_controller::init_from_pci_device(pci_device),
+        (0x01, 0x06, 0x01, _, _, _) if is_enabled("ahci_controller") => {
+            ahci_controller::init_from_pci_device(pci_device)
+        }
 
         _ => {
             log::info!("  Unknown PCI device  (class {class:X}, subclass {subclass:X}, vendor {vendor:X}, device id {device_id:X}, subsystem id {subsystem_id:X}), prog if {prog_if:X}");
         }
     }
 }
+
+/// Drivers can be turned off from the command line, e.g.
+/// `drivers.disable=ahci_controller,pc_keyboard`.
+fn is_enabled(driver: &str) -> bool {
+    let enabled = !cmdline::list("drivers.disable").any(|name| name == driver);
+
+    if !enabled {
+        log::info!("  Driver {driver} is disabled on the command line");
+    }
+
+    enabled
+}
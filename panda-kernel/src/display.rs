@@ -3,6 +3,8 @@ use fontdue::{Font, FontSettings};
 use panda_loader_lib::FrameBuffer;
 use spin::Mutex;
 
+use crate::cmdline;
+
 pub enum FontSize {
     Regular,
     Large,
@@ -75,8 +77,8 @@ impl Display {
     }
 
     pub fn clear_screen(&mut self) {
-        for x in 0..self.frame_buffer.resolution.0 {
-            for y in 0..self.frame_buffer.resolution.1 {
+        for x in 0..self.frame_buffer.width {
+            for y in 0..self.frame_buffer.height {
                 self.frame_buffer.draw_pixel((x, y), (0, 0, 0));
             }
         }
@@ -89,8 +91,8 @@ impl Display {
     }
 
     pub fn scroll_up(&mut self, offset: usize) {
-        for pixel_y in 0..self.frame_buffer.resolution.1 {
-            for pixel_x in 0..self.frame_buffer.resolution.0 {
+        for pixel_y in 0..self.frame_buffer.height {
+            for pixel_x in 0..self.frame_buffer.width {
                 let source_position = (pixel_x, pixel_y + offset);
                 let destination_position = (pixel_x, pixel_y);
 
@@ -117,16 +119,16 @@ impl Display {
                 let font = &self.fonts[*style as usize];
                 let (metrics, bitmap) = font.rasterize(c, size.size());
 
-                if self.position.0 + metrics.width > self.frame_buffer.resolution.0 {
+                if self.position.0 + metrics.width > self.frame_buffer.width {
                     self.write_newline(size);
                 }
 
                 let offset_y =
                     size.size() as usize - metrics.height as usize - metrics.ymin as usize;
 
-                if self.position.1 + metrics.height + offset_y > self.frame_buffer.resolution.1 {
-                    let diff = (self.position.1 + metrics.height + offset_y)
-                        - self.frame_buffer.resolution.1;
+                if self.position.1 + metrics.height + offset_y > self.frame_buffer.height {
+                    let diff =
+                        (self.position.1 + metrics.height + offset_y) - self.frame_buffer.height;
                     self.scroll_up(diff);
                 }
 
@@ -160,9 +162,19 @@ impl core::fmt::Write for Display {
 pub static DISPLAY: OnceCell<Mutex<Display>> = OnceCell::uninit();
 
 pub fn init(frame_buffer: FrameBuffer) {
+    if cmdline::flag("nodisplay") {
+        log::info!("Display disabled on the command line");
+        return;
+    }
+
+    // booted without one, e.g. by a Multiboot2 boot loader in text mode
+    if frame_buffer.width == 0 || frame_buffer.height == 0 {
+        log::info!("No frame buffer, so no display");
+        return;
+    }
+
     let display = Display::new(frame_buffer);
     DISPLAY.init_once(|| Mutex::new(display));
-    clear_screen();
 }
 
 pub fn clear_screen() {
@@ -0,0 +1,425 @@
+//! UEFI runtime services, which the firmware keeps around after the loader
+//! has exited boot services.
+
+mod pstore;
+mod runtime;
+
+use alloc::{string::String, vec, vec::Vec};
+use panda_loader_lib::{
+    BootSlot, FirmwareTables, BOOT_ATTEMPTS_ATTRIBUTES, BOOT_ATTEMPTS_VARIABLE,
+    PANDA_VARIABLE_VENDOR,
+};
+use spin::{Mutex, MutexGuard, Once};
+use x86_64::{
+    instructions::{interrupts, port::Port},
+    structures::paging::{PageSize, PageTableFlags, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use crate::memory::{self, MemoryError, Protection};
+
+pub use self::pstore::save_panic_report;
+use self::runtime::{
+    ConfigurationTable, MemoryAttributesTable, MemoryDescriptor, RuntimeServicesTable, SystemTable,
+};
+pub use self::runtime::{Guid, ResetType, Status, Time};
+
+/// Runtime services memory is mapped here, at the same offset from this
+/// address as it is from the start of physical memory.
+const RUNTIME_VIRTUAL_BASE: u64 = 0xFFFF_D000_0000_0000;
+
+/// The longest variable name the kernel reads or writes, in UCS-2 characters.
+const MAX_VARIABLE_NAME_LENGTH: usize = 31;
+
+/// The firmware isn't reentrant, so only one runtime service can be running
+/// at a time.
+static RUNTIME_SERVICES: Once<Mutex<&'static RuntimeServicesTable>> = Once::new();
+
+static BOOT_SLOT: Once<BootSlot> = Once::new();
+
+#[derive(Debug)]
+pub enum FirmwareError {
+    NotAvailable,
+    Busy,
+    MemoryError(MemoryError),
+    Status(Status),
+}
+
+impl From<MemoryError> for FirmwareError {
+    fn from(error: MemoryError) -> Self {
+        FirmwareError::MemoryError(error)
+    }
+}
+
+/// Maps the firmware's runtime services memory and tells the firmware where
+/// it is with `SetVirtualAddressMap`, so that runtime services keep working
+/// once the identity map is gone. Must be called before
+/// `memory::release_identity_map`.
+pub fn init(tables: &FirmwareTables, boot_slot: BootSlot) -> Result<(), FirmwareError> {
+    BOOT_SLOT.call_once(|| boot_slot);
+
+    let Some(system_table_address) = tables.efi_system_table() else {
+        log::info!("No EFI system table, runtime services aren't available");
+        return Ok(());
+    };
+
+    let system_table = memory::physical_to_virtual(system_table_address).as_ptr::<SystemTable>();
+    let memory_map = tables.efi_memory_map;
+    let descriptors = memory::physical_to_virtual(PhysAddr::new(memory_map.address));
+
+    for offset in (0..memory_map.size).step_by(memory_map.descriptor_size) {
+        let descriptor = unsafe { &mut *(descriptors + offset).as_mut_ptr::<MemoryDescriptor>() };
+
+        if descriptor.attribute & MemoryDescriptor::ATTRIBUTE_RUNTIME != 0 {
+            map_runtime_region(descriptor)?;
+        }
+    }
+
+    let runtime_services = unsafe {
+        let address = PhysAddr::new((*system_table).runtime_services as u64);
+        memory::physical_memory_ref::<RuntimeServicesTable>(address)
+    };
+
+    let memory_attributes = unsafe { memory_attributes_table(&*system_table) };
+
+    // the firmware runs from physical addresses until this returns, which is
+    // why the identity map still has to be there
+    let status = interrupts::without_interrupts(|| unsafe {
+        (runtime_services.set_virtual_address_map)(
+            memory_map.size,
+            memory_map.descriptor_size,
+            memory_map.descriptor_version,
+            descriptors.as_mut_ptr(),
+        )
+    });
+
+    if !status.is_success() {
+        return Err(FirmwareError::Status(status));
+    }
+
+    // only the memory attributes table says which parts of the runtime code
+    // regions are really code, so without one they're left as they are
+    match memory_attributes {
+        Some(table) => table.descriptors().for_each(protect_runtime_image),
+        None => log::info!("No EFI memory attributes table, runtime code stays writable"),
+    }
+
+    // the firmware points the system table at the runtime services table's
+    // new address as part of the switch
+    let runtime_services = unsafe {
+        let runtime_services = core::ptr::addr_of!((*system_table).runtime_services);
+        &*runtime_services.read_volatile()
+    };
+
+    log::info!("UEFI runtime services at {runtime_services:p}");
+    RUNTIME_SERVICES.call_once(|| Mutex::new(runtime_services));
+
+    match get_time() {
+        Ok(time) => log::info!("Firmware clock says it's {time}"),
+        Err(error) => log::warn!("Could not read the firmware clock: {error:?}"),
+    }
+
+    Ok(())
+}
+
+fn map_runtime_region(descriptor: &mut MemoryDescriptor) -> Result<(), FirmwareError> {
+    let phys_start = PhysAddr::new(descriptor.physical_start);
+    let virt_start = VirtAddr::new(RUNTIME_VIRTUAL_BASE + descriptor.physical_start);
+    let size = descriptor.number_of_pages * Size4KiB::SIZE;
+
+    let flags = match descriptor.memory_type {
+        // runtime driver images keep their data in here along with their
+        // code, so this has to be writable as well as executable
+        MemoryDescriptor::RUNTIME_SERVICES_CODE => PageTableFlags::WRITABLE,
+
+        MemoryDescriptor::MEMORY_MAPPED_IO | MemoryDescriptor::MEMORY_MAPPED_IO_PORT_SPACE => {
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | PageTableFlags::NO_CACHE
+        }
+
+        _ => PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    };
+
+    log::debug!(
+        "Mapping EFI runtime region {phys_start:?}-{end:?} (type {memory_type}) at {virt_start:?}",
+        end = phys_start + size,
+        memory_type = descriptor.memory_type
+    );
+
+    unsafe { memory::map_physical_range(phys_start, virt_start, size, flags)? };
+    descriptor.virtual_start = virt_start.as_u64();
+
+    Ok(())
+}
+
+/// Finds the `EFI_MEMORY_ATTRIBUTES_TABLE`, if the firmware has one. Has to
+/// be called while the system table still has physical addresses in it.
+unsafe fn memory_attributes_table(
+    system_table: &SystemTable,
+) -> Option<&'static MemoryAttributesTable> {
+    let entries = core::slice::from_raw_parts(
+        memory::physical_to_virtual(PhysAddr::new(system_table.configuration_table as u64))
+            .as_ptr::<ConfigurationTable>(),
+        system_table.number_of_table_entries,
+    );
+
+    let entry = entries
+        .iter()
+        .find(|entry| entry.vendor_guid == MemoryAttributesTable::GUID)?;
+
+    Some(memory::physical_memory_ref(PhysAddr::new(
+        entry.vendor_table,
+    )))
+}
+
+/// Gives part of a runtime driver image the permissions the memory
+/// attributes table asks for.
+fn protect_runtime_image(descriptor: &MemoryDescriptor) {
+    let read_only = descriptor.attribute & MemoryDescriptor::ATTRIBUTE_READ_ONLY != 0;
+    let no_execute = descriptor.attribute & MemoryDescriptor::ATTRIBUTE_EXECUTE_PROTECT != 0;
+
+    let protection = match (read_only, no_execute) {
+        (true, true) => Protection::ReadOnly,
+        (true, false) => Protection::ReadExecute,
+        (false, true) => Protection::ReadWrite,
+        (false, false) => return,
+    };
+
+    let start = VirtAddr::new(RUNTIME_VIRTUAL_BASE + descriptor.physical_start);
+    let end = start + descriptor.number_of_pages * Size4KiB::SIZE;
+
+    if let Err(error) = unsafe { memory::protect(start, end, protection) } {
+        log::warn!(
+            "Could not make EFI runtime memory at {start:?}-{end:?} {protection:?}: {error:?}"
+        );
+    }
+}
+
+fn runtime_services() -> Result<MutexGuard<'static, &'static RuntimeServicesTable>, FirmwareError> {
+    RUNTIME_SERVICES
+        .get()
+        .map(Mutex::lock)
+        .ok_or(FirmwareError::NotAvailable)
+}
+
+/// Like `runtime_services`, but fails with `Busy` rather than waiting for
+/// a call that's already in progress.
+fn try_runtime_services(
+) -> Result<MutexGuard<'static, &'static RuntimeServicesTable>, FirmwareError> {
+    RUNTIME_SERVICES
+        .get()
+        .ok_or(FirmwareError::NotAvailable)?
+        .try_lock()
+        .ok_or(FirmwareError::Busy)
+}
+
+/// Variable names are null terminated UCS-2. This doesn't allocate, so that
+/// it can be used while panicking.
+fn variable_name(name: &str) -> [u16; MAX_VARIABLE_NAME_LENGTH + 1] {
+    debug_assert!(name.encode_utf16().count() <= MAX_VARIABLE_NAME_LENGTH);
+
+    let mut buffer = [0; MAX_VARIABLE_NAME_LENGTH + 1];
+    for (slot, c) in buffer[..MAX_VARIABLE_NAME_LENGTH]
+        .iter_mut()
+        .zip(name.encode_utf16())
+    {
+        *slot = c;
+    }
+
+    buffer
+}
+
+/// Reads a UEFI variable into `buffer`, returning its size and attributes.
+#[allow(dead_code)]
+pub fn get_variable(
+    name: &str,
+    vendor: &Guid,
+    buffer: &mut [u8],
+) -> Result<(usize, u32), FirmwareError> {
+    let runtime_services = runtime_services()?;
+    let name = variable_name(name);
+    let mut attributes = 0;
+    let mut size = buffer.len();
+
+    let status = interrupts::without_interrupts(|| unsafe {
+        (runtime_services.get_variable)(
+            name.as_ptr(),
+            vendor,
+            &mut attributes,
+            &mut size,
+            buffer.as_mut_ptr(),
+        )
+    });
+
+    match status {
+        Status::SUCCESS => Ok((size, attributes)),
+        status => Err(FirmwareError::Status(status)),
+    }
+}
+
+/// Writes a UEFI variable. Writing an empty value deletes it.
+pub fn set_variable(
+    name: &str,
+    vendor: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), FirmwareError> {
+    write_variable(*runtime_services()?, name, vendor, attributes, data)
+}
+
+/// Like `set_variable`, but gives up rather than waiting if another runtime
+/// service call is in progress, which might be what's panicking.
+fn try_set_variable(
+    name: &str,
+    vendor: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), FirmwareError> {
+    let runtime_services = try_runtime_services()?;
+    write_variable(*runtime_services, name, vendor, attributes, data)
+}
+
+fn write_variable(
+    runtime_services: &RuntimeServicesTable,
+    name: &str,
+    vendor: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), FirmwareError> {
+    let name = variable_name(name);
+
+    let status = interrupts::without_interrupts(|| unsafe {
+        (runtime_services.set_variable)(
+            name.as_ptr(),
+            vendor,
+            attributes,
+            data.len(),
+            data.as_ptr(),
+        )
+    });
+
+    match status {
+        Status::SUCCESS => Ok(()),
+        status => Err(FirmwareError::Status(status)),
+    }
+}
+
+/// The names and vendors of all the UEFI variables that are visible at
+/// runtime.
+#[allow(dead_code)]
+pub fn variable_names() -> Result<Vec<(String, Guid)>, FirmwareError> {
+    let runtime_services = runtime_services()?;
+    let mut names = Vec::new();
+
+    // the firmware carries on from the name and vendor that are already in
+    // these, starting from an empty name
+    let mut name = vec![0u16; 64];
+    let mut vendor = Guid::default();
+
+    loop {
+        let mut size = name.len() * core::mem::size_of::<u16>();
+
+        let status = interrupts::without_interrupts(|| unsafe {
+            (runtime_services.get_next_variable_name)(&mut size, name.as_mut_ptr(), &mut vendor)
+        });
+
+        match status {
+            Status::SUCCESS => {
+                let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                names.push((String::from_utf16_lossy(&name[..length]), vendor));
+            }
+
+            // the previous name has to stay in the buffer for the retry
+            Status::BUFFER_TOO_SMALL => name.resize(size.div_ceil(core::mem::size_of::<u16>()), 0),
+
+            Status::NOT_FOUND => return Ok(names),
+            status => return Err(FirmwareError::Status(status)),
+        }
+    }
+}
+
+/// Reads the real time clock.
+pub fn get_time() -> Result<Time, FirmwareError> {
+    let runtime_services = runtime_services()?;
+    let mut time = Time::default();
+
+    let status = interrupts::without_interrupts(|| unsafe {
+        (runtime_services.get_time)(&mut time, core::ptr::null_mut())
+    });
+
+    match status {
+        Status::SUCCESS => Ok(time),
+        status => Err(FirmwareError::Status(status)),
+    }
+}
+
+/// Sets the real time clock.
+#[allow(dead_code)]
+pub fn set_time(time: &Time) -> Result<(), FirmwareError> {
+    let runtime_services = runtime_services()?;
+
+    let status = interrupts::without_interrupts(|| unsafe { (runtime_services.set_time)(time) });
+
+    match status {
+        Status::SUCCESS => Ok(()),
+        status => Err(FirmwareError::Status(status)),
+    }
+}
+
+/// Resets or turns off the machine. Without runtime services, or if they're
+/// in the middle of a call that may never finish, this falls back to
+/// pulsing the keyboard controller's reset line, or to just halting if it
+/// was meant to turn off.
+#[allow(dead_code)]
+pub fn reset(reset_type: ResetType) -> ! {
+    interrupts::disable();
+    log::info!("Resetting the machine ({reset_type:?})");
+
+    if let Ok(runtime_services) = try_runtime_services() {
+        unsafe {
+            (runtime_services.reset_system)(reset_type, Status::SUCCESS, 0, core::ptr::null())
+        }
+    }
+
+    if reset_type != ResetType::Shutdown {
+        unsafe { Port::<u8>::new(0x64).write(0xFE) };
+    }
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[allow(dead_code)]
+pub fn reboot() -> ! {
+    reset(ResetType::Cold)
+}
+
+#[allow(dead_code)]
+pub fn power_off() -> ! {
+    reset(ResetType::Shutdown)
+}
+
+/// Tells the loader that the kernel came up properly, so that it carries on
+/// booting slot A rather than falling back to slot B.
+pub fn mark_boot_successful() -> Result<(), FirmwareError> {
+    match BOOT_SLOT.get() {
+        Some(BootSlot::A) => {
+            set_variable(
+                BOOT_ATTEMPTS_VARIABLE,
+                &PANDA_VARIABLE_VENDOR,
+                BOOT_ATTEMPTS_ATTRIBUTES,
+                &[0],
+            )?;
+
+            log::info!("Marked slot A as booting successfully");
+        }
+
+        // slot A stays marked as failed until its boot attempts are reset,
+        // e.g. by installing a new kernel there
+        Some(BootSlot::B) => log::warn!("Running from fallback slot B"),
+
+        Some(BootSlot::None) | None => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+//! Saves the end of the console output in a UEFI variable when the kernel
+//! panics, so that it isn't lost on machines without a serial console. The
+//! loader shows it on the next boot.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use panda_loader_lib::{
+    PANDA_VARIABLE_VENDOR, PANIC_REPORT_ATTRIBUTES, PANIC_REPORT_MAX_SIZE, PANIC_REPORT_VARIABLE,
+};
+use spin::Mutex;
+
+use crate::console;
+
+static SAVING: AtomicBool = AtomicBool::new(false);
+
+// the heap might be what's broken, so the report is put together here
+static REPORT: Mutex<[u8; PANIC_REPORT_MAX_SIZE]> = Mutex::new([0; PANIC_REPORT_MAX_SIZE]);
+
+/// Saves everything printed so far, which should end with the panic message
+/// and backtrace. Only the first panic is saved.
+pub fn save_panic_report() {
+    if SAVING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let mut report = REPORT.lock();
+    let length = console::copy_log_tail(&mut *report);
+
+    match super::try_set_variable(
+        PANIC_REPORT_VARIABLE,
+        &PANDA_VARIABLE_VENDOR,
+        PANIC_REPORT_ATTRIBUTES,
+        &report[..length],
+    ) {
+        Ok(()) => println!("Saved the panic report in {PANIC_REPORT_VARIABLE}"),
+        Err(error) => println!("Could not save the panic report: {error:?}"),
+    }
+}
@@ -0,0 +1,217 @@
+//! The parts of the UEFI system table and runtime services table that the
+//! kernel uses, laid out as in the UEFI specification.
+
+/// An `EFI_GUID`, as its raw bytes.
+pub type Guid = [u8; 16];
+
+/// An `EFI_STATUS`.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub usize);
+
+impl Status {
+    const ERROR_BIT: usize = 1 << (usize::BITS - 1);
+
+    pub const SUCCESS: Status = Status(0);
+    pub const BUFFER_TOO_SMALL: Status = Status(Self::ERROR_BIT | 5);
+    pub const NOT_FOUND: Status = Status(Self::ERROR_BIT | 14);
+
+    pub fn is_success(&self) -> bool {
+        *self == Self::SUCCESS
+    }
+}
+
+#[repr(C)]
+pub struct TableHeader {
+    pub signature: u64,
+    pub revision: u32,
+    pub header_size: u32,
+    pub crc32: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+pub struct SystemTable {
+    pub header: TableHeader,
+    pub firmware_vendor: *const u16,
+    pub firmware_revision: u32,
+    pub console_in_handle: usize,
+    pub con_in: usize,
+    pub console_out_handle: usize,
+    pub con_out: usize,
+    pub standard_error_handle: usize,
+    pub std_err: usize,
+    pub runtime_services: *const RuntimeServicesTable,
+    pub boot_services: usize,
+    pub number_of_table_entries: usize,
+    pub configuration_table: usize,
+}
+
+/// Services the kernel doesn't call are left as plain addresses.
+#[repr(C)]
+pub struct RuntimeServicesTable {
+    pub header: TableHeader,
+    pub get_time:
+        unsafe extern "win64" fn(time: *mut Time, capabilities: *mut TimeCapabilities) -> Status,
+    pub set_time: unsafe extern "win64" fn(time: *const Time) -> Status,
+    pub get_wakeup_time: usize,
+    pub set_wakeup_time: usize,
+    pub set_virtual_address_map: unsafe extern "win64" fn(
+        memory_map_size: usize,
+        descriptor_size: usize,
+        descriptor_version: u32,
+        virtual_map: *mut MemoryDescriptor,
+    ) -> Status,
+    pub convert_pointer: usize,
+    pub get_variable: unsafe extern "win64" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut u8,
+    ) -> Status,
+    pub get_next_variable_name: unsafe extern "win64" fn(
+        name_size: *mut usize,
+        name: *mut u16,
+        vendor: *mut Guid,
+    ) -> Status,
+    pub set_variable: unsafe extern "win64" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *const u8,
+    ) -> Status,
+    pub get_next_high_monotonic_count: usize,
+    pub reset_system: unsafe extern "win64" fn(
+        reset_type: ResetType,
+        status: Status,
+        data_size: usize,
+        data: *const u8,
+    ) -> !,
+    pub update_capsule: usize,
+    pub query_capsule_capabilities: usize,
+    pub query_variable_info: usize,
+}
+
+/// An `EFI_TIME`. The firmware keeps the real time clock in local time, with
+/// `time_zone` as how many minutes local time is ahead of UTC.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub pad1: u8,
+    pub nanosecond: u32,
+    pub time_zone: i16,
+    pub daylight: u8,
+    pub pad2: u8,
+}
+
+impl Time {
+    /// The time zone isn't known, so the time is just local time.
+    pub const UNSPECIFIED_TIME_ZONE: i16 = 0x07FF;
+}
+
+impl core::fmt::Display for Time {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+
+        match self.time_zone {
+            Time::UNSPECIFIED_TIME_ZONE => Ok(()),
+            offset => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let (hours, minutes) = (offset.abs() / 60, offset.abs() % 60);
+                write!(f, " UTC{sign}{hours:02}:{minutes:02}")
+            }
+        }
+    }
+}
+
+/// An `EFI_TIME_CAPABILITIES`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeCapabilities {
+    pub resolution: u32,
+    pub accuracy: u32,
+    pub sets_to_zero: bool,
+}
+
+/// An `EFI_RESET_TYPE`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum ResetType {
+    Cold,
+    Warm,
+    Shutdown,
+}
+
+/// An `EFI_MEMORY_DESCRIPTOR`. The firmware's descriptors can be bigger than
+/// this, so step through its memory map by the descriptor size it reports.
+#[repr(C)]
+pub struct MemoryDescriptor {
+    pub memory_type: u32,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+impl MemoryDescriptor {
+    pub const RUNTIME_SERVICES_CODE: u32 = 5;
+    pub const MEMORY_MAPPED_IO: u32 = 11;
+    pub const MEMORY_MAPPED_IO_PORT_SPACE: u32 = 12;
+
+    /// No code may run from this memory.
+    pub const ATTRIBUTE_EXECUTE_PROTECT: u64 = 0x4000;
+    /// Nothing may write to this memory.
+    pub const ATTRIBUTE_READ_ONLY: u64 = 0x20000;
+    /// The firmware needs this memory to be mapped for runtime services.
+    pub const ATTRIBUTE_RUNTIME: u64 = 1 << 63;
+}
+
+/// An `EFI_CONFIGURATION_TABLE` entry.
+#[repr(C)]
+pub struct ConfigurationTable {
+    pub vendor_guid: Guid,
+    pub vendor_table: u64,
+}
+
+/// The header of an `EFI_MEMORY_ATTRIBUTES_TABLE`, which is followed by
+/// memory descriptors splitting the runtime regions up into the code and
+/// data of each runtime driver image.
+#[repr(C)]
+pub struct MemoryAttributesTable {
+    pub version: u32,
+    pub number_of_entries: u32,
+    pub descriptor_size: u32,
+    pub reserved: u32,
+}
+
+impl MemoryAttributesTable {
+    /// dcfa911d-26eb-469f-a220-38b7dc461220
+    pub const GUID: Guid = [
+        0x1D, 0x91, 0xFA, 0xDC, 0xEB, 0x26, 0x9F, 0x46, 0xA2, 0x20, 0x38, 0xB7, 0xDC, 0x46, 0x12,
+        0x20,
+    ];
+
+    pub fn descriptors(&self) -> impl Iterator<Item = &MemoryDescriptor> {
+        let start = (self as *const Self).wrapping_add(1).cast::<u8>();
+        let descriptor_size = self.descriptor_size as usize;
+
+        (0..self.number_of_entries as usize).map(move |index| unsafe {
+            &*start
+                .add(index * descriptor_size)
+                .cast::<MemoryDescriptor>()
+        })
+    }
+}
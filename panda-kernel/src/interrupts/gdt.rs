@@ -0,0 +1,38 @@
+use spin::Once;
+use x86_64::{
+    instructions::segmentation::{Segment, CS, DS, ES, SS},
+    structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+};
+
+struct Gdt {
+    table: GlobalDescriptorTable,
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+}
+
+static GDT: Once<Gdt> = Once::new();
+
+/// Loads our own GDT. The firmware's lives in memory that's only reachable
+/// through the identity map, which the kernel gets rid of.
+pub fn init() {
+    let gdt = GDT.call_once(|| {
+        let mut table = GlobalDescriptorTable::new();
+        let code_selector = table.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = table.add_entry(Descriptor::kernel_data_segment());
+
+        Gdt {
+            table,
+            code_selector,
+            data_selector,
+        }
+    });
+
+    gdt.table.load();
+
+    unsafe {
+        CS::set_reg(gdt.code_selector);
+        SS::set_reg(gdt.data_selector);
+        DS::set_reg(gdt.data_selector);
+        ES::set_reg(gdt.data_selector);
+    }
+}
@@ -1,12 +1,18 @@
+mod gdt;
 mod page_fault;
 
 use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
 
 use self::page_fault::page_fault_handler;
+use crate::backtrace;
 
 static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
 
 pub fn init() {
+    // handlers are installed with whatever code segment is current, so this
+    // has to come first
+    gdt::init();
+
     unsafe {
         IDT.page_fault.set_handler_fn(page_fault_handler);
         IDT.double_fault.set_handler_fn(double_fault_handler);
@@ -32,6 +38,7 @@ pub extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) -> ! {
+    backtrace::print_exception_backtrace(stack_frame.instruction_pointer);
     panic!(
         "EXCEPTION: DOUBLE FAULT\n{:#?}\nError code: {:X}",
         stack_frame, error_code
@@ -42,6 +49,7 @@ pub extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    backtrace::print_exception_backtrace(stack_frame.instruction_pointer);
     panic!(
         "EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}\nError code: {:X}",
         stack_frame, error_code
@@ -6,17 +6,17 @@ use x86_64::{
     },
 };
 
-use crate::memory;
+use crate::{backtrace, memory};
 
 pub extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     let address = Cr2::read();
     let page = Page::containing_address(address);
 
-    match address.as_u64() {
-        0xD0000000..=0xE0000000 => {
+    match address {
+        address if memory::is_heap_address(address) => {
             let frame = memory::allocate_frame().expect("Failed to allocate frame");
             memory::map_page_to_frame(page, frame).expect("Failed to map page to frame");
         }
@@ -24,6 +24,7 @@ pub extern "x86-interrupt" fn page_fault_handler(
             println!("EXCEPTION: INVALID PAGE FAULT");
             println!("Error code: {:?}", error_code);
             println!("Faulting address: {:?}", address);
+            backtrace::print_exception_backtrace(stack_frame.instruction_pointer);
             panic!("Invalid page fault");
         }
     }
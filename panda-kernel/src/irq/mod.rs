@@ -3,21 +3,30 @@ mod interrupts;
 use core::ops::Range;
 
 use acpi::InterruptModel;
+use alloc::vec::Vec;
 use linked_list_allocator::LockedHeap;
+use spin::Once;
 use x2apic::{
     ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry},
     lapic::{IpiDestMode, LocalApic, TimerDivide, TimerMode},
 };
-use x86_64::{structures::idt::HandlerFunc, PhysAddr};
+use x86_64::{structures::idt::HandlerFunc, PhysAddr, VirtAddr};
 
 use crate::{
     interrupts::install_interrupt_handler,
     irq::interrupts::{lapic_error_handler, lapic_spurious_handler, lapic_timer_handler},
-    memory,
+    memory::vma::{ioremap, CacheType},
 };
 
 static mut INTERRUPT_MODEL: Option<InterruptModel<'static, &LockedHeap>> = None;
 
+// where the APICs' registers are mapped, by `init`
+static LAPIC_BASE: Once<VirtAddr> = Once::new();
+static IOAPIC_BASES: Once<Vec<Option<VirtAddr>>> = Once::new();
+
+/// Each APIC's registers fit in a page.
+const APIC_REGISTERS_SIZE: u64 = 0x1000;
+
 const TIMER_VECTOR: usize = 0x20;
 const ERROR_VECTOR: usize = 0x21;
 const SPURIOUS_VECTOR: usize = 0x22;
@@ -29,9 +38,7 @@ pub enum ApicError {
 }
 
 pub fn lapic() -> Result<LocalApic, ApicError> {
-    if let Some(InterruptModel::Apic(apic)) = unsafe { &INTERRUPT_MODEL } {
-        let base_address = memory::physical_to_virtual(PhysAddr::new(apic.local_apic_address));
-
+    if let Some(base_address) = LAPIC_BASE.get() {
         let apic = x2apic::lapic::LocalApicBuilder::new()
             .set_xapic_base(base_address.as_u64())
             .ipi_destination_mode(IpiDestMode::Logical)
@@ -57,19 +64,39 @@ unsafe fn ioapic_indexes() -> Result<Range<usize>, ApicError> {
     }
 }
 unsafe fn ioapic(index: usize) -> Result<IoApic, ApicError> {
-    match &INTERRUPT_MODEL {
-        Some(InterruptModel::Apic(apic)) => {
-            let base_address =
-                memory::physical_to_virtual(PhysAddr::new(apic.io_apics[index].address as u64));
+    match IOAPIC_BASES.get().and_then(|bases| bases.get(index)) {
+        Some(Some(base_address)) => Ok(IoApic::new(base_address.as_u64())),
+        Some(None) => Err(ApicError::ApicError("IO APIC registers not mapped")),
+        None => Err(ApicError::NoApic),
+    }
+}
 
-            let ioapic = IoApic::new(base_address.as_u64());
-            Ok(ioapic)
+fn map_apic_registers(address: u64) -> Option<VirtAddr> {
+    let address = PhysAddr::new(address);
+
+    match unsafe { ioremap(address, APIC_REGISTERS_SIZE, CacheType::Uncached) } {
+        Ok(base_address) => Some(base_address),
+        Err(error) => {
+            log::error!("Could not map APIC registers at {address:?}: {error:?}");
+            None
         }
-        _ => todo!(),
     }
 }
 
 pub fn init(interrupt_model: InterruptModel<'static, &'static LockedHeap>) {
+    if let InterruptModel::Apic(apic) = &interrupt_model {
+        if let Some(base_address) = map_apic_registers(apic.local_apic_address) {
+            LAPIC_BASE.call_once(|| base_address);
+        }
+
+        IOAPIC_BASES.call_once(|| {
+            apic.io_apics
+                .iter()
+                .map(|io_apic| map_apic_registers(io_apic.address as u64))
+                .collect()
+        });
+    }
+
     unsafe {
         INTERRUPT_MODEL = Some(interrupt_model);
     }
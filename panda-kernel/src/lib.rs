@@ -11,17 +11,23 @@
 extern crate alloc;
 
 mod acpi;
+mod boot_modules;
 #[macro_use]
 mod console;
+mod backtrace;
+mod cmdline;
 mod devices;
 mod display;
 mod error;
+mod firmware;
 mod interrupts;
 mod irq;
 mod logger;
 mod memory;
+mod multiboot;
 mod panic;
 mod pci;
+mod splash;
 mod task;
 mod util;
 
@@ -29,7 +35,9 @@ pub use crate::console::_print;
 
 use display::{FontSize, FontStyle, TextPart};
 use error::KernelError;
-use panda_loader_lib::{KernelEntryFn, LoaderCarePackage};
+use panda_loader_lib::{
+    BootStage, KernelEntryFn, KernelVerification, LoaderCarePackage, MemoryDescriptorType,
+};
 
 extern crate core;
 
@@ -41,19 +49,66 @@ pub extern "win64" fn _start(care_package: &LoaderCarePackage) {
 
 fn kernel_init(care_package: &LoaderCarePackage) -> Result<(), KernelError> {
     console::init();
+    care_package.validate()?;
+
+    cmdline::init(care_package.command_line());
+    backtrace::init(care_package.symbols(), care_package.kernel_slide());
     logger::init()?;
-    interrupts::init();
+    splash::init(care_package.frame_buffer(), care_package.splash());
+    log::debug!("Firmware tables: {:?}", care_package.firmware_tables());
 
-    care_package.validate()?;
+    match care_package.kernel_verification() {
+        KernelVerification::Verified => log::info!("Kernel signature verified by the loader"),
+        verification => log::warn!("Kernel signature not verified: {verification:?}"),
+    }
+
+    interrupts::init();
 
     memory::init(
-        &care_package.memory_map,
-        care_package.phys_memory_virt_offset,
+        care_package.memory_map(),
+        care_package.phys_memory_virt_offset(),
     )?;
-    display::init(care_package.frame_buffer.clone());
+    multiboot::protect_kernel_image();
+    splash::advance(BootStage::MemoryReady);
+
+    boot_modules::init(care_package.modules());
+
+    // the kernel gets by without runtime services, they're only needed for
+    // the clock, variables and resetting
+    if let Err(error) = firmware::init(care_package.firmware_tables(), care_package.boot_slot()) {
+        log::error!("Could not set up UEFI runtime services: {error:?}");
+    }
+    splash::advance(BootStage::FirmwareReady);
+
+    display::init(care_package.frame_buffer().clone());
+    splash::advance(BootStage::DisplayReady);
+
     task::init();
+    splash::advance(BootStage::TasksReady);
 
-    let _ = acpi::init(care_package.rsdp_address)?;
+    let _ = acpi::init(care_package.firmware_tables().rsdp())?;
+    splash::advance(BootStage::AcpiReady);
+
+    // everything from the loader is reachable through the direct map, so
+    // the lower half can be cleared out
+    unsafe {
+        memory::release_identity_map();
+    }
+
+    // the memory map is in the loader's data, which is about to be reclaimed
+    let memory_map = care_package.memory_map().to_vec();
+
+    // the care package itself is on the loader's stack, which is in boot
+    // services memory, so it can't be used after this. Everything else
+    // needed from the loader and the ACPI tables has been copied by now.
+    memory::reclaim(
+        &memory_map,
+        &[
+            MemoryDescriptorType::BootServicesReclaimable,
+            MemoryDescriptorType::AcpiReclaimable,
+            MemoryDescriptorType::LoaderReclaimable,
+        ],
+    );
 
     Ok(())
 }
@@ -61,9 +116,16 @@ fn kernel_init(care_package: &LoaderCarePackage) -> Result<(), KernelError> {
 fn kernel_main() -> Result<(), KernelError> {
     x86_64::instructions::interrupts::enable();
 
+    // the splash stays up until now, so the display starts out blank
+    splash::advance(BootStage::Done);
+    display::clear_screen();
     display::write_text(TextPart("Panda OS\n", FontSize::Large, FontStyle::Bold));
     log::info!("Looks like everything's working!");
 
+    if let Err(error) = firmware::mark_boot_successful() {
+        log::warn!("Could not mark the boot as successful: {error:?}");
+    }
+
     loop {
         task::step();
 
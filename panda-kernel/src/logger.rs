@@ -1,4 +1,6 @@
-use log::Level;
+use log::LevelFilter;
+
+use crate::cmdline;
 
 #[derive(Debug)]
 pub enum LoggerError {
@@ -12,7 +14,16 @@ impl From<log::SetLoggerError> for LoggerError {
 }
 
 pub fn init() -> Result<(), LoggerError> {
-    log::set_max_level(log::LevelFilter::Trace);
+    // the level can be set with e.g. `log=debug` on the command line
+    let level = match cmdline::get("log") {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            println!("Invalid log level {value:?}, using info");
+            LevelFilter::Info
+        }),
+        None => LevelFilter::Info,
+    };
+
+    log::set_max_level(level);
     log::set_logger(&ConsoleLogger)?;
     Ok(())
 }
@@ -21,7 +32,7 @@ struct ConsoleLogger;
 
 impl log::Log for ConsoleLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &log::Record) {
@@ -0,0 +1,192 @@
+use core::{
+    alloc::Layout,
+    arch::x86_64::_mm_clflush,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use x86_64::{
+    structures::paging::{frame::PhysFrameRange, PageSize, PageTableFlags, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::{vma::CacheType, MemoryError, Zone};
+
+/// DMA buffers are mapped here, offset by their physical address, so each
+/// one's virtual address follows from its frames.
+const DMA_VIRTUAL_BASE: u64 = 0xFFFF_E000_0000_0000;
+
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Memory for a device to read and write directly. It's physically
+/// contiguous, starts on a page boundary, comes from whichever zone the
+/// device can address, and is mapped uncached so that neither side sees
+/// stale data. The direct map of its frames is made uncached too while the
+/// buffer is alive, since the CPU mustn't see the same memory with two
+/// different cache types.
+pub struct DmaBuffer<T: ?Sized> {
+    frames: PhysFrameRange,
+    value: NonNull<T>,
+}
+
+// the buffer owns its value as much as a Box would
+unsafe impl<T: ?Sized + Send> Send for DmaBuffer<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for DmaBuffer<T> {}
+
+impl<T> DmaBuffer<T> {
+    /// Moves `value` into a new buffer.
+    pub fn new(value: T, zone: Zone) -> Result<Self, MemoryError> {
+        let frames = allocate(Layout::new::<T>(), zone)?;
+        let ptr = virtual_address(frames).as_mut_ptr::<T>();
+
+        unsafe {
+            ptr.write(value);
+        }
+
+        Ok(DmaBuffer {
+            frames,
+            value: NonNull::new(ptr).unwrap(),
+        })
+    }
+}
+
+impl<T: Copy> DmaBuffer<[T]> {
+    /// A buffer of `len` copies of `value`.
+    pub fn from_elem(value: T, len: usize, zone: Zone) -> Result<Self, MemoryError> {
+        let layout = Layout::array::<T>(len).map_err(|_| MemoryError::OutOfMemory)?;
+        let frames = allocate(layout, zone)?;
+        let start = virtual_address(frames).as_mut_ptr::<T>();
+
+        for index in 0..len {
+            unsafe {
+                start.add(index).write(value);
+            }
+        }
+
+        Ok(DmaBuffer {
+            frames,
+            value: NonNull::slice_from_raw_parts(NonNull::new(start).unwrap(), len),
+        })
+    }
+}
+
+impl<T: ?Sized> DmaBuffer<T> {
+    /// The address to give the device.
+    pub fn physical_address(&self) -> PhysAddr {
+        self.frames.start.start_address()
+    }
+
+    pub fn virtual_address(&self) -> VirtAddr {
+        virtual_address(self.frames)
+    }
+
+    /// The address to give the device for part of the buffer, e.g. one
+    /// element of an array.
+    pub fn physical_address_of<U: ?Sized>(&self, part: &U) -> PhysAddr {
+        let offset = VirtAddr::from_ptr(part as *const U) - self.virtual_address();
+        assert!(
+            offset < self.size(),
+            "{part:p} isn't part of the DMA buffer",
+            part = part as *const U
+        );
+
+        self.physical_address() + offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.frames.end.start_address() - self.frames.start.start_address()
+    }
+}
+
+impl<T: ?Sized> Deref for DmaBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for DmaBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for DmaBuffer<T> {
+    /// The device mustn't be using the buffer any more.
+    fn drop(&mut self) {
+        unsafe {
+            core::ptr::drop_in_place(self.value.as_ptr());
+
+            if let Err(error) = super::unmap_range(self.virtual_address(), self.size()) {
+                log::error!("Could not unmap a DMA buffer, leaking it: {error:?}");
+                return;
+            }
+
+            release(self.frames);
+        }
+    }
+}
+
+fn allocate(layout: Layout, zone: Zone) -> Result<PhysFrameRange, MemoryError> {
+    // anything with a stricter alignment than a page would need frames
+    // allocated on that boundary
+    assert!(layout.align() as u64 <= Size4KiB::SIZE);
+
+    let count = (layout.size() as u64).div_ceil(Size4KiB::SIZE).max(1);
+    let frames = super::allocate_frames(count as usize, zone).ok_or(MemoryError::OutOfMemory)?;
+
+    let physical_address = frames.start.start_address();
+    let size = count * Size4KiB::SIZE;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | PageTableFlags::NO_CACHE;
+
+    unsafe {
+        if let Err(error) = set_direct_map_cache_type(frames, CacheType::Uncached) {
+            // puts back whichever pages were changed before it failed
+            release(frames);
+            return Err(error);
+        }
+
+        // the frames may still have dirty lines from whatever used them last,
+        // which would otherwise get written back over the device's data
+        let direct_map = super::physical_to_virtual(physical_address);
+        for offset in (0..size).step_by(CACHE_LINE_SIZE) {
+            _mm_clflush((direct_map + offset).as_ptr());
+        }
+
+        // map_physical_range unmaps whatever it managed to map if it fails
+        if let Err(error) =
+            super::map_physical_range(physical_address, virtual_address(frames), size, flags)
+        {
+            release(frames);
+            return Err(error);
+        }
+    }
+
+    Ok(frames)
+}
+
+/// Makes the direct map of `frames` write-back again and frees them.
+unsafe fn release(frames: PhysFrameRange) {
+    if let Err(error) = set_direct_map_cache_type(frames, CacheType::WriteBack) {
+        log::error!("Could not make DMA frames cacheable again, leaking them: {error:?}");
+        return;
+    }
+
+    super::deallocate_frames(frames);
+}
+
+unsafe fn set_direct_map_cache_type(
+    frames: PhysFrameRange,
+    cache_type: CacheType,
+) -> Result<(), MemoryError> {
+    super::set_cache_type(
+        super::physical_to_virtual(frames.start.start_address()),
+        super::physical_to_virtual(frames.end.start_address()),
+        cache_type,
+    )
+}
+
+fn virtual_address(frames: PhysFrameRange) -> VirtAddr {
+    VirtAddr::new(DMA_VIRTUAL_BASE + frames.start.start_address().as_u64())
+}
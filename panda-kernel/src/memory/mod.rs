@@ -1,33 +1,74 @@
-mod frame_allocator;
+pub mod dma;
+pub mod vma;
 
 #[cfg(not(test))]
 use core::alloc::Layout;
-use core::mem::size_of;
 
+use frame_allocator::PhysicalAllocator;
 use linked_list_allocator::LockedHeap;
-use panda_loader_lib::MemoryDescriptor;
+use panda_loader_lib::{MemoryDescriptor, MemoryDescriptorType};
+use spin::Mutex;
 use x86_64::{
+    instructions::interrupts,
     structures::paging::{
-        mapper::{MapToError, TranslateResult, UnmapError},
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
-        PhysFrame, Size2MiB, Size4KiB, Translate,
+        frame::PhysFrameRange,
+        mapper::{FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 
-use self::frame_allocator::PhysicalAllocator;
+use self::vma::CacheType;
+
+pub use frame_allocator::{FrameStats, Zone};
 
 #[global_allocator]
 pub static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
 
-static mut FRAME_ALLOCATOR: PhysicalAllocator = PhysicalAllocator::new();
+/// Only ever locked through `with_frame_allocator`.
+static FRAME_ALLOCATOR: Mutex<PhysicalAllocator> = Mutex::new(PhysicalAllocator::new());
 
 static mut PHYSICAL_MEMORY_VIRTUAL_BASE: VirtAddr = unsafe { VirtAddr::new_unsafe(0x000000000) };
 
+/// The kernel heap lives above the direct map of physical memory. Pages are
+/// mapped in on demand by the page fault handler.
+const HEAP_START: u64 = 0xFFFF_C000_0000_0000;
+const HEAP_SIZE: u64 = 0x1000_0000;
+
 #[derive(Debug)]
 pub enum MemoryError {
+    OutOfMemory,
+    OutOfVirtualMemory,
+    NotMapped(VirtAddr),
+    AlreadyMapped(PhysAddr),
+    InvalidRange(PhysAddr),
     UnmapError(UnmapError),
     MapToError4KiB(MapToError<Size4KiB>),
+    FlagUpdateError(FlagUpdateError),
+}
+
+/// Access permissions for a range of kernel memory. Writable memory is never
+/// executable and vice versa.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    ReadOnly,
+    ReadWrite,
+    ReadExecute,
+}
+
+impl Protection {
+    fn apply(&self, flags: PageTableFlags) -> PageTableFlags {
+        let mut flags = flags - (PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+
+        match self {
+            Protection::ReadOnly => flags |= PageTableFlags::NO_EXECUTE,
+            Protection::ReadWrite => flags |= PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Protection::ReadExecute => {}
+        }
+
+        flags
+    }
 }
 
 impl From<UnmapError> for MemoryError {
@@ -42,6 +83,12 @@ impl From<MapToError<Size4KiB>> for MemoryError {
     }
 }
 
+impl From<FlagUpdateError> for MemoryError {
+    fn from(error: FlagUpdateError) -> Self {
+        MemoryError::FlagUpdateError(error)
+    }
+}
+
 pub unsafe fn page_table() -> &'static mut PageTable {
     // read from CR3 register
     let (cr3, _flags) = x86_64::registers::control::Cr3::read();
@@ -59,8 +106,37 @@ pub unsafe fn page_mapper() -> OffsetPageTable<'static> {
     OffsetPageTable::new(page_table(), PHYSICAL_MEMORY_VIRTUAL_BASE)
 }
 
+/// Runs `f` with the frame allocator locked. Interrupts are kept off until
+/// it's unlocked, as an interrupt handler that allocates, or that touches a
+/// heap page that isn't mapped in yet, would otherwise spin on the lock
+/// forever.
+fn with_frame_allocator<R>(f: impl FnOnce(&mut PhysicalAllocator) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut FRAME_ALLOCATOR.lock()))
+}
+
 pub fn allocate_frame() -> Option<PhysFrame> {
-    unsafe { FRAME_ALLOCATOR.allocate_frame() }
+    with_frame_allocator(|allocator| allocator.allocate_frame())
+}
+
+/// Allocates `count` physically contiguous frames from `zone`.
+pub fn allocate_frames(count: usize, zone: Zone) -> Option<PhysFrameRange> {
+    with_frame_allocator(|allocator| allocator.allocate_frames(count, zone))
+}
+
+/// Gives a frame from `allocate_frame` back. Nothing may use it afterwards.
+#[allow(dead_code)]
+pub unsafe fn deallocate_frame(frame: PhysFrame) {
+    with_frame_allocator(|allocator| allocator.deallocate_frame(frame))
+}
+
+/// Gives frames from `allocate_frames` back. Nothing may use them
+/// afterwards.
+pub unsafe fn deallocate_frames(range: PhysFrameRange) {
+    with_frame_allocator(|allocator| allocator.deallocate_frames(range))
+}
+
+pub fn frame_stats() -> FrameStats {
+    with_frame_allocator(|allocator| allocator.stats())
 }
 
 pub fn physical_to_virtual(physical_address: PhysAddr) -> VirtAddr {
@@ -73,93 +149,313 @@ pub unsafe fn physical_memory_ref<T>(physical_address: PhysAddr) -> &'static mut
 }
 
 pub fn map_page_to_frame(page: Page, frame: PhysFrame) -> Result<(), MemoryError> {
-    unsafe {
-        let mut mapper = page_mapper();
-        mapper
-            .map_to(
-                page,
-                frame,
-                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
-                &mut FRAME_ALLOCATOR,
-            )?
-            .flush();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    with_frame_allocator(|allocator| unsafe {
+        page_mapper().map_to(page, frame, flags, allocator)
+    })?
+    .flush();
+
+    Ok(())
+}
+
+/// Maps `size` bytes of physical memory starting at `phys_start` to
+/// `virt_start` using 4 KiB pages. Both addresses must be page aligned. If
+/// a page can't be mapped, the ones before it are unmapped again.
+pub unsafe fn map_physical_range(
+    phys_start: PhysAddr,
+    virt_start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MemoryError> {
+    let mut mapper = page_mapper();
+
+    for offset in (0..size).step_by(Size4KiB::SIZE as usize) {
+        let page = Page::<Size4KiB>::containing_address(virt_start + offset);
+        let frame = PhysFrame::containing_address(phys_start + offset);
+
+        let result = with_frame_allocator(|allocator| {
+            mapper.map_to(page, frame, flags | PageTableFlags::PRESENT, allocator)
+        });
+
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(error) => {
+                if let Err(unmap_error) = unmap_range(virt_start, offset) {
+                    log::error!(
+                        "Could not unmap {virt_start:?} after failing to map it: {unmap_error:?}"
+                    );
+                }
+
+                return Err(error.into());
+            }
+        }
     }
 
     Ok(())
 }
 
-pub unsafe fn init_page_table() -> Result<(), MemoryError> {
-    // unmap 0xD0000000 - 0xDFFFFFFF
+/// Unmaps `size` bytes of 4 KiB pages starting at `virt_start`. The frames
+/// they were mapped to are left alone.
+pub unsafe fn unmap_range(virt_start: VirtAddr, size: u64) -> Result<(), MemoryError> {
     let mut mapper = page_mapper();
 
-    for addr in (0xD0000000..0xE0000000).step_by(Size2MiB::SIZE as usize) {
-        let page = Page::<Size2MiB>::containing_address(VirtAddr::new(addr));
-        let (_, flush) = mapper.unmap(page)?;
+    for offset in (0..size).step_by(Size4KiB::SIZE as usize) {
+        let page = Page::<Size4KiB>::containing_address(virt_start + offset);
+        let (_frame, flush) = mapper.unmap(page)?;
         flush.flush();
     }
 
     Ok(())
 }
 
+pub fn is_heap_address(addr: VirtAddr) -> bool {
+    (HEAP_START..HEAP_START + HEAP_SIZE).contains(&addr.as_u64())
+}
+
+/// Removes the identity map of physical memory that the loader needed while
+/// switching to the kernel's page tables, leaving the lower half of the
+/// address space empty. Nothing may use a physical address as a pointer
+/// after this.
+pub unsafe fn release_identity_map() {
+    let page_table = page_table();
+
+    for entry in page_table.iter_mut().take(256) {
+        entry.set_unused();
+    }
+
+    x86_64::instructions::tlb::flush_all();
+}
+
 pub fn init(descriptors: &[MemoryDescriptor], phys_mem_base: VirtAddr) -> Result<(), MemoryError> {
+    print_memory_map(descriptors);
+
     unsafe {
         PHYSICAL_MEMORY_VIRTUAL_BASE = phys_mem_base;
-        FRAME_ALLOCATOR.init(descriptors);
+    }
 
-        init_page_table()?;
+    with_frame_allocator(|allocator| allocator.init(descriptors, phys_mem_base));
 
+    unsafe {
         x86_64::instructions::interrupts::enable();
 
-        let heap_range = 0xD0000000..0xE0000000;
         GLOBAL_ALLOCATOR
             .lock()
-            .init(heap_range.start, heap_range.count());
+            .init(HEAP_START as usize, HEAP_SIZE as usize);
     }
 
+    let stats = frame_stats();
+    log::info!(
+        "{} MiB of physical memory, {} MiB free",
+        stats.total_bytes() / (1024 * 1024),
+        stats.free_bytes() / (1024 * 1024)
+    );
+
     Ok(())
 }
 
+/// Logs the memory map in the style of the BIOS e820 map, merging adjacent
+/// regions of the same type.
+fn print_memory_map(descriptors: &[MemoryDescriptor]) {
+    let mut merged: Option<(PhysAddr, PhysAddr, MemoryDescriptorType)> = None;
+
+    for descriptor in descriptors {
+        let end = descriptor.base_addr + descriptor.length;
+
+        merged = match merged {
+            Some((start, previous_end, memory_type))
+                if previous_end == descriptor.base_addr
+                    && memory_type == descriptor.memory_type =>
+            {
+                Some((start, end, memory_type))
+            }
+
+            previous => {
+                if let Some(region) = previous {
+                    print_memory_region(region);
+                }
+
+                Some((descriptor.base_addr, end, descriptor.memory_type))
+            }
+        };
+    }
+
+    if let Some(region) = merged {
+        print_memory_region(region);
+    }
+}
+
+fn print_memory_region((start, end, memory_type): (PhysAddr, PhysAddr, MemoryDescriptorType)) {
+    log::info!(
+        "mem [{:#018x}-{:#018x}] {:?}",
+        start.as_u64(),
+        end.as_u64() - 1,
+        memory_type
+    );
+}
+
+/// Hands memory of the given types over to the frame allocator, once
+/// whatever was in it is no longer needed. The descriptors mustn't be in any
+/// of that memory.
+pub fn reclaim(descriptors: &[MemoryDescriptor], memory_types: &[MemoryDescriptorType]) {
+    let mut total = 0;
+
+    for &memory_type in memory_types {
+        let bytes =
+            with_frame_allocator(|allocator| allocator.add_regions(descriptors, memory_type));
+        log::info!("Reclaimed {} KiB of {:?} memory", bytes / 1024, memory_type);
+        total += bytes;
+    }
+
+    log::info!(
+        "Reclaimed {} KiB in total, {} MiB free",
+        total / 1024,
+        frame_stats().free_bytes() / (1024 * 1024)
+    );
+}
+
 #[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error(layout: Layout) -> ! {
     panic!("Allocation error: {:?}", layout);
 }
 
-pub(crate) unsafe fn mark_deref_as_uncacheable<T>(ptr: *const T) {
-    let start_address = VirtAddr::from_ptr(ptr);
-    mark_as_uncacheable(start_address, start_address + size_of::<T>())
+/// Changes the access permissions of every page overlapping
+/// `start_address..end_address`. The pages must already be mapped. Huge
+/// pages that stick out of the range are split up, so nothing outside it
+/// changes.
+pub unsafe fn protect(
+    start_address: VirtAddr,
+    end_address: VirtAddr,
+    protection: Protection,
+) -> Result<(), MemoryError> {
+    update_range_flags(start_address, end_address, |flags| protection.apply(flags))
 }
 
-pub(crate) unsafe fn mark_as_uncacheable(start_address: VirtAddr, end_address: VirtAddr) {
+/// Changes how the CPU caches every page overlapping
+/// `start_address..end_address`, in the same way as `protect`. Going from
+/// write-back to anything else doesn't write back or drop lines that are
+/// already cached, which is up to the caller.
+pub unsafe fn set_cache_type(
+    start_address: VirtAddr,
+    end_address: VirtAddr,
+    cache_type: CacheType,
+) -> Result<(), MemoryError> {
+    update_range_flags(start_address, end_address, |flags| cache_type.apply(flags))
+}
+
+unsafe fn update_range_flags(
+    start_address: VirtAddr,
+    end_address: VirtAddr,
+    update: impl Fn(PageTableFlags) -> PageTableFlags,
+) -> Result<(), MemoryError> {
     let mut mapper = page_mapper();
+    let mut address = start_address.align_down(Size4KiB::SIZE);
 
-    let range = Page::<Size2MiB>::range(
-        Page::containing_address(start_address),
-        Page::containing_address(end_address),
-    );
+    let range_start = address;
+    let range_end = end_address.align_up(Size4KiB::SIZE);
+    let covers = |start: VirtAddr, size: u64| range_start <= start && start + size <= range_end;
+
+    while address < end_address {
+        let (frame, flags) = match mapper.translate(address) {
+            TranslateResult::Mapped { frame, flags, .. } => (frame, flags),
+            TranslateResult::NotMapped | TranslateResult::InvalidFrameAddress(_) => {
+                return Err(MemoryError::NotMapped(address))
+            }
+        };
+
+        let flags = update(flags);
 
-    for page in range {
-        match mapper.translate(page.start_address()) {
-            TranslateResult::Mapped {
-                frame: _,
-                offset: _,
-                mut flags,
-            } => {
-                flags.set(PageTableFlags::NO_CACHE, true);
-
-                mapper
-                    .update_flags(page, flags)
-                    .expect("Failed to update memory page flags")
-                    .flush();
+        // the range might be covered by pages of any size, so step over
+        // whichever kind of page this address is in
+        match frame {
+            MappedFrame::Size4KiB(_) => {
+                let page = Page::<Size4KiB>::containing_address(address);
+                mapper.update_flags(page, flags)?.flush();
+                address = page.start_address() + page.size();
             }
 
-            TranslateResult::NotMapped => todo!(),
-            TranslateResult::InvalidFrameAddress(_) => todo!(),
+            MappedFrame::Size2MiB(_) => {
+                let page = Page::<Size2MiB>::containing_address(address);
+                if !covers(page.start_address(), page.size()) {
+                    split_huge_page(address)?;
+                    continue;
+                }
+
+                mapper.update_flags(page, flags)?.flush();
+                address = page.start_address() + page.size();
+            }
+
+            MappedFrame::Size1GiB(_) => {
+                let page = Page::<Size1GiB>::containing_address(address);
+                if !covers(page.start_address(), page.size()) {
+                    split_huge_page(address)?;
+                    continue;
+                }
+
+                mapper.update_flags(page, flags)?.flush();
+                address = page.start_address() + page.size();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the 2 MiB or 1 GiB page containing `address` with a table of
+/// pages of the next size down, mapping the same memory with the same flags.
+unsafe fn split_huge_page(address: VirtAddr) -> Result<(), MemoryError> {
+    let levels = [
+        (address.p4_index(), 0),
+        (address.p3_index(), Size1GiB::SIZE),
+        (address.p2_index(), Size2MiB::SIZE),
+    ];
+
+    let mut table = page_table();
+
+    for (index, page_size) in levels {
+        let entry = &mut table[index];
+        let flags = entry.flags();
+
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return Err(MemoryError::NotMapped(address));
+        }
+
+        if !flags.contains(PageTableFlags::HUGE_PAGE) {
+            table = physical_memory_ref(entry.addr());
+            continue;
+        }
+
+        let frame = allocate_frame().ok_or(MemoryError::OutOfMemory)?;
+        let new_table: &mut PageTable = physical_memory_ref(frame.start_address());
+
+        // only 2 MiB and 1 GiB pages have the huge page bit
+        let (child_size, child_flags) = if page_size == Size2MiB::SIZE {
+            (Size4KiB::SIZE, flags - PageTableFlags::HUGE_PAGE)
+        } else {
+            (Size2MiB::SIZE, flags)
+        };
+
+        for (child, child_entry) in new_table.iter_mut().enumerate() {
+            child_entry.set_addr(entry.addr() + child as u64 * child_size, child_flags);
         }
+
+        // the pages themselves say what's allowed, the table entry mustn't
+        // take any of it away
+        let table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        entry.set_addr(frame.start_address(), table_flags);
+
+        x86_64::instructions::tlb::flush(address);
+        return Ok(());
     }
+
+    // already mapped with 4 KiB pages
+    Ok(())
 }
 
 pub fn virtual_to_physical(addr: VirtAddr) -> Option<PhysAddr> {
-    let mut mapper = unsafe { page_mapper() };
+    let mapper = unsafe { page_mapper() };
     mapper.translate_addr(addr)
 }
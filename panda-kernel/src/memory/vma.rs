@@ -0,0 +1,135 @@
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{PageSize, PageTableFlags, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::MemoryError;
+
+/// Device memory is mapped somewhere in here, wherever there's room.
+const IO_VIRTUAL_BASE: u64 = 0xFFFF_F000_0000_0000;
+const IO_VIRTUAL_SIZE: u64 = 0x10_0000_0000;
+
+/// Mappings are kept apart by an unmapped page, so running off the end of
+/// one faults rather than reaching into another device's registers.
+const GUARD_SIZE: u64 = Size4KiB::SIZE;
+
+/// Mapped regions of device memory, by the virtual address they start at.
+static REGIONS: Mutex<BTreeMap<u64, Region>> = Mutex::new(BTreeMap::new());
+
+/// How the CPU may cache a mapping of device memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    /// Every read and write goes to the device, in order. What device
+    /// registers need.
+    Uncached,
+    /// Reads may be cached, but writes always go to the device.
+    #[allow(dead_code)]
+    WriteThrough,
+    /// Cached like ordinary memory.
+    WriteBack,
+}
+
+impl CacheType {
+    /// Replaces the caching bits of `flags` with this type's.
+    pub(super) fn apply(&self, flags: PageTableFlags) -> PageTableFlags {
+        (flags - (PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH)) | self.flags()
+    }
+
+    fn flags(&self) -> PageTableFlags {
+        match self {
+            CacheType::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            CacheType::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            CacheType::WriteBack => PageTableFlags::empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    phys_start: PhysAddr,
+    size: u64,
+    cache_type: CacheType,
+}
+
+/// Maps `len` bytes of device memory at `phys` into kernel space, and
+/// returns where `phys` ended up. Fails if any of the pages are already
+/// mapped by an earlier call.
+pub unsafe fn ioremap(
+    phys: PhysAddr,
+    len: u64,
+    cache_type: CacheType,
+) -> Result<VirtAddr, MemoryError> {
+    let phys_start = phys.align_down(Size4KiB::SIZE);
+    let phys_end = phys
+        .as_u64()
+        .checked_add(len.max(1))
+        .and_then(|end| PhysAddr::try_new(end).ok())
+        .ok_or(MemoryError::InvalidRange(phys))?
+        .align_up(Size4KiB::SIZE);
+    let size = phys_end - phys_start;
+    if size > IO_VIRTUAL_SIZE {
+        return Err(MemoryError::OutOfVirtualMemory);
+    }
+
+    let mut regions = REGIONS.lock();
+
+    if let Some(region) = regions.values().find(|region| {
+        region.phys_start < phys_start + size && phys_start < region.phys_start + region.size
+    }) {
+        log::warn!(
+            "Refusing to map {phys:?}, {start:?}-{end:?} is already mapped ({cache_type:?})",
+            start = region.phys_start,
+            end = region.phys_start + region.size,
+            cache_type = region.cache_type
+        );
+        return Err(MemoryError::AlreadyMapped(phys));
+    }
+
+    // the first gap that's big enough
+    let mut virt_start = IO_VIRTUAL_BASE;
+    for (&start, region) in regions.iter() {
+        if virt_start + size + GUARD_SIZE <= start {
+            break;
+        }
+
+        virt_start = start + region.size + GUARD_SIZE;
+    }
+
+    if virt_start + size + GUARD_SIZE > IO_VIRTUAL_BASE + IO_VIRTUAL_SIZE {
+        return Err(MemoryError::OutOfVirtualMemory);
+    }
+
+    let virt_start = VirtAddr::new(virt_start);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | cache_type.flags();
+    // if this fails part way, the pages it did map are unmapped again, so
+    // nothing is left behind outside REGIONS
+    super::map_physical_range(phys_start, virt_start, size, flags)?;
+
+    log::debug!("Mapped {phys_start:?}-{phys_end:?} at {virt_start:?} ({cache_type:?})");
+
+    regions.insert(
+        virt_start.as_u64(),
+        Region {
+            phys_start,
+            size,
+            cache_type,
+        },
+    );
+
+    Ok(virt_start + (phys - phys_start))
+}
+
+/// Unmaps device memory mapped by `ioremap`, given the address it returned.
+#[allow(dead_code)]
+pub unsafe fn iounmap(virt: VirtAddr) -> Result<(), MemoryError> {
+    let mut regions = REGIONS.lock();
+
+    let virt_start = virt.align_down(Size4KiB::SIZE);
+    let region = regions
+        .remove(&virt_start.as_u64())
+        .ok_or(MemoryError::NotMapped(virt))?;
+
+    super::unmap_range(virt_start, region.size)
+}
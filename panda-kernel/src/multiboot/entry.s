@@ -0,0 +1,225 @@
+/*
+ * The Multiboot2 header, and a trampoline from the 32-bit protected mode
+ * that Multiboot2 boot loaders start the kernel in to the long mode, higher
+ * half environment that panda-loader would have set up.
+ *
+ * The kernel is loaded at its physical address (see linker.ld) and this runs
+ * with paging turned off, so nothing here is at the address it was linked
+ * at. Everything is found relative to where the code is actually running.
+ */
+
+.set MULTIBOOT2_HEADER_MAGIC, 0xE85250D6
+.set MULTIBOOT2_BOOTLOADER_MAGIC, 0x36D76289
+.set MULTIBOOT2_ARCHITECTURE_I386, 0
+
+.set MULTIBOOT2_TAG_END, 0
+.set MULTIBOOT2_TAG_ENTRY_ADDRESS, 3
+.set MULTIBOOT2_TAG_FRAMEBUFFER, 5
+.set MULTIBOOT2_TAG_OPTIONAL, 1
+
+.set PAGE_PRESENT_WRITABLE, 0x3
+.set PAGE_PRESENT_WRITABLE_HUGE, 0x83
+
+.set STACK_SIZE, 128 * 1024
+
+.pushsection .multiboot_header, "a"
+.balign 8
+multiboot_header:
+    .long MULTIBOOT2_HEADER_MAGIC
+    .long MULTIBOOT2_ARCHITECTURE_I386
+    .long multiboot_header_end - multiboot_header
+    .long 0x100000000 - (MULTIBOOT2_HEADER_MAGIC + MULTIBOOT2_ARCHITECTURE_I386 + (multiboot_header_end - multiboot_header))
+
+    /* the ELF entry point is panda-loader's, and 64-bit */
+    .balign 8
+    .short MULTIBOOT2_TAG_ENTRY_ADDRESS
+    .short 0
+    .long 12
+    .long MULTIBOOT_ENTRY_PHYSICAL
+
+    /* ask for a 32 bits per pixel frame buffer, like panda-loader sets up */
+    .balign 8
+    .short MULTIBOOT2_TAG_FRAMEBUFFER
+    .short MULTIBOOT2_TAG_OPTIONAL
+    .long 20
+    .long 1024
+    .long 768
+    .long 32
+
+    .balign 8
+    .short MULTIBOOT2_TAG_END
+    .short 0
+    .long 8
+multiboot_header_end:
+.popsection
+
+.pushsection .text.multiboot, "ax"
+.code32
+.global multiboot_entry
+multiboot_entry:
+    cli
+    cld
+
+    call 1f
+1:  pop %ebp
+
+    cmp $MULTIBOOT2_BOOTLOADER_MAGIC, %eax
+    jne multiboot_halt
+
+    /* kept for multiboot_main: the boot information and the physical
+       address of the kernel image */
+    mov %ebx, %edi
+    lea (__kernel_start - 1b)(%ebp), %esi
+
+    lea (multiboot_stack_top - 1b)(%ebp), %esp
+
+    /* identity map the first 4 GiB with 2 MiB pages */
+    lea (multiboot_pd_low - 1b)(%ebp), %ebx
+    xor %ecx, %ecx
+2:  mov %ecx, %eax
+    shl $21, %eax
+    or $PAGE_PRESENT_WRITABLE_HUGE, %eax
+    mov %eax, (%ebx, %ecx, 8)
+    inc %ecx
+    cmp $4 * 512, %ecx
+    jb 2b
+
+    lea (multiboot_pdpt_low - 1b)(%ebp), %edx
+    xor %ecx, %ecx
+3:  mov %ecx, %eax
+    shl $12, %eax
+    add %ebx, %eax
+    or $PAGE_PRESENT_WRITABLE, %eax
+    mov %eax, (%edx, %ecx, 8)
+    inc %ecx
+    cmp $4, %ecx
+    jb 3b
+
+    /* map the kernel image where it's linked, in the top 2 GiB */
+    lea (multiboot_pd_kernel - 1b)(%ebp), %ebx
+    lea (__kernel_end - 1b)(%ebp), %edx
+    sub %esi, %edx
+    add $0x1FFFFF, %edx
+    shr $21, %edx
+    xor %ecx, %ecx
+4:  mov %ecx, %eax
+    shl $21, %eax
+    add %esi, %eax
+    or $PAGE_PRESENT_WRITABLE_HUGE, %eax
+    mov %eax, (%ebx, %ecx, 8)
+    inc %ecx
+    cmp %edx, %ecx
+    jb 4b
+
+    lea (multiboot_pdpt_kernel - 1b)(%ebp), %edx
+    or $PAGE_PRESENT_WRITABLE, %ebx
+    mov %ebx, 510 * 8(%edx)
+
+    /* the first 4 GiB are also the direct map of physical memory, at
+       0xFFFF_8000_0000_0000 */
+    lea (multiboot_pml4 - 1b)(%ebp), %ebx
+    lea (multiboot_pdpt_low - 1b)(%ebp), %eax
+    or $PAGE_PRESENT_WRITABLE, %eax
+    mov %eax, (%ebx)
+    mov %eax, 256 * 8(%ebx)
+    or $PAGE_PRESENT_WRITABLE, %edx
+    mov %edx, 511 * 8(%ebx)
+    mov %ebx, %cr3
+
+    /* PAE, and SSE, which the compiler uses */
+    mov %cr4, %eax
+    or $(1 << 5) | (1 << 9) | (1 << 10), %eax
+    mov %eax, %cr4
+
+    /* long mode, and no-execute pages, which the kernel's mappings use */
+    mov $0xC0000080, %ecx
+    rdmsr
+    or $(1 << 8) | (1 << 11), %eax
+    wrmsr
+
+    /* paging and write protection, and let SSE instructions through */
+    mov %cr0, %eax
+    and $~(1 << 2), %eax
+    or $(1 << 31) | (1 << 16) | (1 << 1), %eax
+    mov %eax, %cr0
+
+    /* still in a 32-bit code segment until CS is reloaded */
+    lea (multiboot_gdt - 1b)(%ebp), %eax
+    mov %eax, (multiboot_gdt_pointer + 2 - 1b)(%ebp)
+    lgdt (multiboot_gdt_pointer - 1b)(%ebp)
+
+    push $0x08
+    lea (multiboot_long_mode - 1b)(%ebp), %eax
+    push %eax
+    lret
+
+multiboot_halt:
+    cli
+    hlt
+    jmp multiboot_halt
+
+.code64
+multiboot_long_mode:
+    mov $0x10, %eax
+    mov %eax, %ds
+    mov %eax, %es
+    mov %eax, %ss
+    xor %eax, %eax
+    mov %eax, %fs
+    mov %eax, %gs
+
+    /* the top halves of these weren't set in 32-bit mode */
+    mov %edi, %edi
+    mov %esi, %esi
+
+    /* move up to where the kernel is linked */
+    lea multiboot_higher_half(%rip), %rax
+    sub %rsi, %rax
+    movabs $KERNEL_VIRTUAL_BASE, %rcx
+    add %rcx, %rax
+    jmp *%rax
+
+multiboot_higher_half:
+    lea multiboot_stack_top(%rip), %rsp
+
+    /* the end of the frame pointer chain, for backtraces */
+    xor %ebp, %ebp
+
+    call multiboot_main
+    jmp multiboot_halt
+.popsection
+
+.pushsection .rodata.multiboot, "a"
+.balign 8
+multiboot_gdt:
+    .quad 0
+    .quad 0x00AF9A000000FFFF /* 64-bit code */
+    .quad 0x00CF92000000FFFF /* data */
+multiboot_gdt_end:
+.popsection
+
+.pushsection .data.multiboot, "aw"
+.balign 8
+multiboot_gdt_pointer:
+    .short multiboot_gdt_end - multiboot_gdt - 1
+    .long 0
+.popsection
+
+.pushsection .bss.multiboot, "aw", @nobits
+.balign 4096
+multiboot_pml4:
+    .skip 4096
+multiboot_pdpt_low:
+    .skip 4096
+multiboot_pdpt_kernel:
+    .skip 4096
+multiboot_pd_low:
+    .skip 4 * 4096
+multiboot_pd_kernel:
+    .skip 4096
+
+.balign 16
+multiboot_stack:
+    .skip STACK_SIZE
+multiboot_stack_top:
+.popsection
@@ -0,0 +1,510 @@
+//! A second way into the kernel, for Multiboot2 boot loaders like GRUB.
+//!
+//! The trampoline in `entry.s` sets things up the way panda-loader would:
+//! long mode, the kernel mapped where it's linked, and physical memory both
+//! identity mapped and mapped at `PHYSICAL_MEMORY_VIRTUAL_BASE`, though only
+//! the first 4 GiB of it. This turns the Multiboot2 boot information into a
+//! care package and carries on from `_start`.
+
+use panda_loader_lib::{
+    BootModule, BootSlot, EfiMemoryMap, FirmwareTables, FrameBuffer, KernelVerification,
+    LoaderCarePackage, MemoryDescriptor, MemoryDescriptorType, PixelBitmask, PixelFormat,
+    SymbolTable,
+};
+use spin::Once;
+use x86_64::{
+    structures::paging::{PageSize, Size2MiB, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use crate::memory::{self, Protection};
+
+core::arch::global_asm!(include_str!("entry.s"), options(att_syntax));
+
+/// Where `entry.s` maps physical memory, which is where panda-loader maps it
+/// too.
+const PHYSICAL_MEMORY_VIRTUAL_BASE: u64 = 0xFFFF_8000_0000_0000;
+
+/// How much physical memory `entry.s` maps. Memory above this is left out of
+/// the memory map.
+const PHYSICAL_MEMORY_MAPPED: u64 = 0x1_0000_0000;
+
+/// The BIOS keeps its data in the first megabyte, and the legacy video memory
+/// is there too.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+const MAX_MEMORY_DESCRIPTORS: usize = 128;
+const MAX_MODULES: usize = 16;
+
+const TAG_END: u32 = 0;
+const TAG_COMMAND_LINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAME_BUFFER: u32 = 8;
+const TAG_EFI64_SYSTEM_TABLE: u32 = 12;
+const TAG_ACPI_OLD_RSDP: u32 = 14;
+const TAG_ACPI_NEW_RSDP: u32 = 15;
+const TAG_EFI_MEMORY_MAP: u32 = 17;
+
+/// A 64 bit base and length and a 32 bit type. Entries can be longer, for
+/// later versions of the spec.
+const MEMORY_MAP_ENTRY_MIN_SIZE: usize = 20;
+
+const MEMORY_AVAILABLE: u32 = 1;
+const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+const MEMORY_ACPI_NVS: u32 = 4;
+const MEMORY_BAD: u32 = 5;
+
+const FRAME_BUFFER_DIRECT_COLOUR: u8 = 1;
+
+extern "C" {
+    // from linker.ld
+    static __kernel_start: u8;
+    static __rodata_start: u8;
+    static __data_start: u8;
+    static __kernel_end: u8;
+}
+
+// the care package points into this, so it has to stay around
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+/// Called by `entry.s` with the physical addresses of the boot information
+/// and of the kernel image.
+#[no_mangle]
+extern "C" fn multiboot_main(info_address: u64, kernel_physical_start: u64) -> ! {
+    let boot_info = BOOT_INFO.call_once(|| unsafe {
+        BootInfo::parse(
+            PhysAddr::new(info_address),
+            PhysAddr::new(kernel_physical_start),
+        )
+    });
+
+    crate::_start(&boot_info.care_package());
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// `entry.s` can't tell the kernel's sections apart, so it maps the whole
+/// image, and the rest of the 2 MiB page it ends in, writable and
+/// executable, along with all of the direct map. Once the kernel can change
+/// its own page tables, this gives everything the permissions panda-loader
+/// would have. Does nothing if the kernel didn't come in this way.
+pub fn protect_kernel_image() {
+    if BOOT_INFO.get().is_none() {
+        return;
+    }
+
+    let (kernel_start, rodata_start, data_start, kernel_end) = unsafe {
+        (
+            VirtAddr::from_ptr(&__kernel_start),
+            VirtAddr::from_ptr(&__rodata_start),
+            VirtAddr::from_ptr(&__data_start),
+            VirtAddr::from_ptr(&__kernel_end),
+        )
+    };
+
+    let direct_map = VirtAddr::new(PHYSICAL_MEMORY_VIRTUAL_BASE);
+
+    let regions = [
+        (kernel_start, rodata_start, Protection::ReadExecute),
+        (rodata_start, data_start, Protection::ReadOnly),
+        (data_start, kernel_end, Protection::ReadWrite),
+        (
+            kernel_end.align_up(Size4KiB::SIZE),
+            kernel_end.align_up(Size2MiB::SIZE),
+            Protection::ReadWrite,
+        ),
+        (
+            direct_map,
+            direct_map + PHYSICAL_MEMORY_MAPPED,
+            Protection::ReadWrite,
+        ),
+    ];
+
+    for (start, end, protection) in regions {
+        if let Err(error) = unsafe { memory::protect(start, end, protection) } {
+            log::error!("Could not make {start:?}-{end:?} {protection:?}: {error:?}");
+        }
+    }
+}
+
+/// What's needed from the boot information, in the shape the care package
+/// wants it.
+struct BootInfo {
+    memory_map: [MemoryDescriptor; MAX_MEMORY_DESCRIPTORS],
+    memory_map_len: usize,
+    modules: [BootModule; MAX_MODULES],
+    modules_len: usize,
+    command_line: &'static str,
+    frame_buffer: FrameBuffer,
+    firmware_tables: FirmwareTables,
+}
+
+impl BootInfo {
+    /// The boot information has to be somewhere `entry.s` mapped. It's left
+    /// where it is and marked as reserved, since the command line and ACPI
+    /// RSDP are used from there.
+    unsafe fn parse(info_address: PhysAddr, kernel_physical_start: PhysAddr) -> Self {
+        let info = physical_to_virtual(info_address).as_ptr::<u8>();
+        let total_size = (info as *const u32).read() as usize;
+        let info = core::slice::from_raw_parts(info, total_size);
+
+        let kernel_size = &__kernel_end as *const u8 as u64 - &__kernel_start as *const u8 as u64;
+
+        let mut boot_info = BootInfo {
+            memory_map: [MemoryDescriptor {
+                base_addr: PhysAddr::zero(),
+                length: 0,
+                memory_type: MemoryDescriptorType::Reserved,
+            }; MAX_MEMORY_DESCRIPTORS],
+            memory_map_len: 0,
+            modules: [BootModule::new("", PhysAddr::zero(), 0); MAX_MODULES],
+            modules_len: 0,
+            command_line: "",
+            frame_buffer: FrameBuffer {
+                base_addr: 0,
+                width: 0,
+                height: 0,
+                stride: 0,
+                pixel_format: PixelFormat::BGR,
+            },
+            firmware_tables: FirmwareTables::default(),
+        };
+
+        let mut efi_system_table_address = 0;
+        let mut memory_map = None;
+
+        for (tag_type, tag) in Tags::new(info) {
+            match tag_type {
+                TAG_COMMAND_LINE => boot_info.command_line = c_string(tag),
+                TAG_MODULE => boot_info.add_module(tag),
+                TAG_MEMORY_MAP => memory_map = Some(tag),
+                TAG_FRAME_BUFFER => {
+                    if let Some(frame_buffer) = frame_buffer(tag) {
+                        boot_info.frame_buffer = frame_buffer;
+                    }
+                }
+                TAG_EFI64_SYSTEM_TABLE => efi_system_table_address = read_u64(tag, 0),
+
+                // the RSDP itself is copied into the tag
+                TAG_ACPI_OLD_RSDP if boot_info.firmware_tables.rsdp_address == 0 => {
+                    boot_info.firmware_tables.rsdp_address = virtual_to_physical(tag).as_u64();
+                    boot_info.firmware_tables.rsdp_revision = 0;
+                }
+                TAG_ACPI_NEW_RSDP => {
+                    boot_info.firmware_tables.rsdp_address = virtual_to_physical(tag).as_u64();
+                    boot_info.firmware_tables.rsdp_revision = 2;
+                }
+
+                // only there if the boot loader exited boot services, which
+                // is also when runtime services can be used
+                TAG_EFI_MEMORY_MAP => {
+                    boot_info.firmware_tables.efi_memory_map = EfiMemoryMap {
+                        address: virtual_to_physical(&tag[8..]).as_u64(),
+                        size: tag.len() - 8,
+                        descriptor_size: read_u32(tag, 0) as usize,
+                        descriptor_version: read_u32(tag, 4),
+                    };
+                }
+
+                _ => {}
+            }
+        }
+
+        // the firmware still runs from physical addresses while it's told
+        // where it's been mapped, so all of it has to be in the part of
+        // memory that entry.s identity maps
+        let efi_memory_map = boot_info.firmware_tables.efi_memory_map;
+        if efi_memory_map.size != 0 && runtime_regions_mapped(&efi_memory_map) {
+            boot_info.firmware_tables.efi_system_table_address = efi_system_table_address;
+        }
+
+        if let Some(memory_map) = memory_map {
+            let kernel = (kernel_physical_start.as_u64(), kernel_size);
+            let info = (info_address.as_u64(), total_size as u64);
+            boot_info.build_memory_map(memory_map, kernel, info);
+        }
+
+        boot_info
+    }
+
+    fn add_module(&mut self, tag: &'static [u8]) {
+        if self.modules_len == MAX_MODULES {
+            return;
+        }
+
+        if tag.len() < 8 {
+            log::warn!("Ignoring a module tag that's too short");
+            return;
+        }
+
+        let start = read_u32(tag, 0) as u64;
+        let end = read_u32(tag, 4) as u64;
+
+        if end < start {
+            log::warn!("Ignoring a module that ends ({end:#X}) before it starts ({start:#X})");
+            return;
+        }
+
+        // the string is the module's command line, which starts with its path
+        let path = c_string(&tag[8..]).split_whitespace().next().unwrap_or("");
+        let name = path.rsplit('/').next().unwrap_or(path);
+
+        self.modules[self.modules_len] = BootModule::new(name, PhysAddr::new(start), end - start);
+        self.modules_len += 1;
+    }
+
+    /// Converts the boot loader's memory map, taking the kernel image, its
+    /// modules and the boot information itself out of the available memory.
+    fn build_memory_map(&mut self, tag: &'static [u8], kernel: (u64, u64), info: (u64, u64)) {
+        let mut claims = [(0, 0, MemoryDescriptorType::Reserved); MAX_MODULES + 4];
+        claims[0] = (0, LOW_MEMORY_END, MemoryDescriptorType::Reserved);
+        claims[1] = (
+            kernel.0,
+            kernel.0 + kernel.1,
+            MemoryDescriptorType::LoaderKernel,
+        );
+        claims[2] = (info.0, info.0 + info.1, MemoryDescriptorType::Reserved);
+        claims[3] = (
+            PHYSICAL_MEMORY_MAPPED,
+            u64::MAX,
+            MemoryDescriptorType::Reserved,
+        );
+
+        for (claim, module) in claims[4..]
+            .iter_mut()
+            .zip(&self.modules[..self.modules_len])
+        {
+            let start = module.base_addr.as_u64();
+            *claim = (
+                start,
+                start + module.size,
+                MemoryDescriptorType::LoaderModules,
+            );
+        }
+
+        let claims = &mut claims[..4 + self.modules_len];
+        claims.sort_unstable_by_key(|(start, _, _)| *start);
+
+        let entry_size = if tag.len() < 8 {
+            0
+        } else {
+            read_u32(tag, 0) as usize
+        };
+
+        if entry_size < MEMORY_MAP_ENTRY_MIN_SIZE {
+            log::warn!("Ignoring a memory map with {entry_size} byte entries");
+            return;
+        }
+
+        for entry in tag[8..].chunks_exact(entry_size) {
+            let start = read_u64(entry, 0);
+            let end = start.saturating_add(read_u64(entry, 8));
+
+            let memory_type = match read_u32(entry, 16) {
+                MEMORY_AVAILABLE => {
+                    self.add_available(start, end, claims);
+                    continue;
+                }
+                MEMORY_ACPI_RECLAIMABLE => MemoryDescriptorType::AcpiReclaimable,
+                MEMORY_ACPI_NVS => MemoryDescriptorType::AcpiNvs,
+                MEMORY_BAD => MemoryDescriptorType::Unusable,
+                _ => MemoryDescriptorType::Reserved,
+            };
+
+            self.add_region(start, end, memory_type);
+        }
+    }
+
+    /// Adds available memory, apart from the parts of it that are claimed.
+    /// `claims` must be sorted and mustn't overlap.
+    fn add_available(
+        &mut self,
+        mut start: u64,
+        end: u64,
+        claims: &[(u64, u64, MemoryDescriptorType)],
+    ) {
+        for &(claim_start, claim_end, memory_type) in claims {
+            if claim_end <= start || claim_start >= end {
+                continue;
+            }
+
+            self.add_available_pages(start, claim_start);
+            self.add_region(start.max(claim_start), end.min(claim_end), memory_type);
+            start = end.min(claim_end);
+        }
+
+        self.add_available_pages(start, end);
+    }
+
+    /// The frame allocator only deals in whole pages.
+    fn add_available_pages(&mut self, start: u64, end: u64) {
+        let start = x86_64::align_up(start, Size4KiB::SIZE);
+        let end = x86_64::align_down(end, Size4KiB::SIZE);
+
+        if start < end {
+            self.add_region(start, end, MemoryDescriptorType::Available);
+        }
+    }
+
+    fn add_region(&mut self, start: u64, end: u64, memory_type: MemoryDescriptorType) {
+        if start >= end || self.memory_map_len == MAX_MEMORY_DESCRIPTORS {
+            return;
+        }
+
+        self.memory_map[self.memory_map_len] = MemoryDescriptor {
+            base_addr: PhysAddr::new(start),
+            length: end - start,
+            memory_type,
+        };
+        self.memory_map_len += 1;
+    }
+
+    fn care_package(&'static self) -> LoaderCarePackage {
+        LoaderCarePackage::from_static(
+            self.frame_buffer.clone(),
+            &self.memory_map[..self.memory_map_len],
+            VirtAddr::new(PHYSICAL_MEMORY_VIRTUAL_BASE),
+            self.firmware_tables,
+            self.command_line,
+            &self.modules[..self.modules_len],
+            SymbolTable::empty(),
+            0,
+            // nothing checked the kernel's signature
+            KernelVerification::Unsigned,
+            BootSlot::None,
+            false,
+        )
+    }
+}
+
+/// Whether every region of memory the firmware keeps for runtime services
+/// is below `PHYSICAL_MEMORY_MAPPED`.
+unsafe fn runtime_regions_mapped(memory_map: &EfiMemoryMap) -> bool {
+    const ATTRIBUTE_RUNTIME: u64 = 1 << 63;
+    const DESCRIPTOR_SIZE: usize = 40;
+
+    if memory_map.descriptor_size < DESCRIPTOR_SIZE {
+        return false;
+    }
+
+    let descriptors = physical_to_virtual(PhysAddr::new(memory_map.address)).as_ptr::<u8>();
+    let descriptors = core::slice::from_raw_parts(descriptors, memory_map.size);
+
+    descriptors
+        .chunks_exact(memory_map.descriptor_size)
+        .filter(|descriptor| read_u64(descriptor, 32) & ATTRIBUTE_RUNTIME != 0)
+        .all(|descriptor| {
+            let start = read_u64(descriptor, 8);
+            let size = read_u64(descriptor, 24).saturating_mul(Size4KiB::SIZE);
+            start.saturating_add(size) <= PHYSICAL_MEMORY_MAPPED
+        })
+}
+
+/// Only direct colour frame buffers with 32-bit pixels are any use, since
+/// that's all the kernel can draw on.
+fn frame_buffer(tag: &[u8]) -> Option<FrameBuffer> {
+    let address = read_u64(tag, 0);
+    let pitch = read_u32(tag, 8) as u64;
+    let width = read_u32(tag, 12) as u64;
+    let height = read_u32(tag, 16) as u64;
+    let bits_per_pixel = tag[20];
+    let frame_buffer_type = tag[21];
+
+    if frame_buffer_type != FRAME_BUFFER_DIRECT_COLOUR || bits_per_pixel != 32 {
+        return None;
+    }
+
+    if address + pitch * height > PHYSICAL_MEMORY_MAPPED {
+        return None;
+    }
+
+    let channel = |offset: usize| (tag[offset], tag[offset + 1]);
+    let pixel_format = match (channel(24), channel(26), channel(28)) {
+        ((16, 8), (8, 8), (0, 8)) => PixelFormat::BGR,
+        ((0, 8), (8, 8), (16, 8)) => PixelFormat::RGB,
+        (red, green, blue) => {
+            let mask = |(position, size): (u8, u8)| (((1u64 << size) - 1) << position) as u32;
+            let (red, green, blue) = (mask(red), mask(green), mask(blue));
+
+            PixelFormat::Bitmask(PixelBitmask {
+                red,
+                green,
+                blue,
+                reserved: !(red | green | blue),
+            })
+        }
+    };
+
+    Some(FrameBuffer {
+        base_addr: physical_to_virtual(PhysAddr::new(address)).as_u64() as usize,
+        width: width as usize,
+        height: height as usize,
+        stride: (pitch / 4) as usize,
+        pixel_format,
+    })
+}
+
+/// The tags in the boot information, as their types and contents.
+struct Tags {
+    info: &'static [u8],
+    offset: usize,
+}
+
+impl Tags {
+    fn new(info: &'static [u8]) -> Self {
+        // after the total size and a reserved field
+        Tags { info, offset: 8 }
+    }
+}
+
+impl Iterator for Tags {
+    type Item = (u32, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset + 8 > self.info.len() {
+            return None;
+        }
+
+        let tag_type = read_u32(self.info, self.offset);
+        let size = read_u32(self.info, self.offset + 4) as usize;
+
+        if tag_type == TAG_END || size < 8 {
+            return None;
+        }
+
+        let contents = self.info.get(self.offset + 8..self.offset + size)?;
+
+        // tags start on 8 byte boundaries
+        self.offset = x86_64::align_up((self.offset + size) as u64, 8) as usize;
+
+        Some((tag_type, contents))
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// A null terminated string, or nothing if it isn't UTF-8.
+fn c_string(bytes: &'static [u8]) -> &'static str {
+    let length = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..length]).unwrap_or("")
+}
+
+fn physical_to_virtual(address: PhysAddr) -> VirtAddr {
+    VirtAddr::new(address.as_u64() + PHYSICAL_MEMORY_VIRTUAL_BASE)
+}
+
+fn virtual_to_physical(bytes: &[u8]) -> PhysAddr {
+    PhysAddr::new(bytes.as_ptr() as u64 - PHYSICAL_MEMORY_VIRTUAL_BASE)
+}
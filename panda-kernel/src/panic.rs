@@ -2,6 +2,8 @@
 #[panic_handler]
 pub fn panic_handler(_info: &core::panic::PanicInfo) -> ! {
     println!("Panic: {}", _info);
+    crate::backtrace::print_backtrace();
+    crate::firmware::save_panic_report();
     loop {
         x86_64::instructions::hlt();
     }
@@ -1,13 +1,25 @@
 use acpi::PciConfigRegions;
+use alloc::collections::BTreeMap;
 use core::mem;
 use linked_list_allocator::LockedHeap;
-use spin::once::Once;
-use x86_64::PhysAddr;
+use spin::{once::Once, Mutex};
+use x86_64::{PhysAddr, VirtAddr};
 
-use crate::memory;
+use crate::memory::{
+    vma::{ioremap, CacheType},
+    MemoryError,
+};
 
 static PCI_CONFIG_REGIONS: Once<PciConfigRegions<'static, &'static LockedHeap>> = Once::new();
 
+/// Where each bus's configuration space is mapped, by segment and bus. Buses
+/// are mapped the first time they're accessed.
+static ECAM_BUSES: Mutex<BTreeMap<(u16, u8), VirtAddr>> = Mutex::new(BTreeMap::new());
+
+/// 32 devices with 8 functions each, and 4 KiB of configuration space for
+/// each function.
+const ECAM_BUS_SIZE: u64 = 0x10_0000;
+
 pub fn init(pci_config_regions: PciConfigRegions<'static, &'static LockedHeap>) {
     PCI_CONFIG_REGIONS.call_once(move || pci_config_regions);
 
@@ -183,21 +195,54 @@ impl PciDevice {
 pub enum PciError {
     NotInitialized,
     InvalidPciAddress,
+    MemoryError(MemoryError),
 }
 
-pub(crate) fn read<T: 'static + Copy>(
+impl From<MemoryError> for PciError {
+    fn from(error: MemoryError) -> Self {
+        PciError::MemoryError(error)
+    }
+}
+
+/// Where a function's configuration space register at `offset` is mapped.
+fn config_address(
     segment: u16,
     bus: u8,
     device: u8,
     function: u8,
     offset: u16,
-) -> Result<T, PciError> {
+) -> Result<VirtAddr, PciError> {
     let pci_config_regions = PCI_CONFIG_REGIONS.get().ok_or(PciError::NotInitialized)?;
 
-    let base_addr = pci_config_regions
+    let bus_addr = pci_config_regions
+        .physical_address(segment, bus, 0, 0)
+        .ok_or(PciError::InvalidPciAddress)?;
+    let function_addr = pci_config_regions
         .physical_address(segment, bus, device, function)
         .ok_or(PciError::InvalidPciAddress)?;
-    let addr = memory::physical_to_virtual(PhysAddr::new(base_addr + offset as u64));
+
+    let mut buses = ECAM_BUSES.lock();
+    let bus_base = match buses.get(&(segment, bus)) {
+        Some(bus_base) => *bus_base,
+        None => {
+            let bus_base =
+                unsafe { ioremap(PhysAddr::new(bus_addr), ECAM_BUS_SIZE, CacheType::Uncached)? };
+            buses.insert((segment, bus), bus_base);
+            bus_base
+        }
+    };
+
+    Ok(bus_base + (function_addr - bus_addr) + offset as u64)
+}
+
+pub(crate) fn read<T: 'static + Copy>(
+    segment: u16,
+    bus: u8,
+    device: u8,
+    function: u8,
+    offset: u16,
+) -> Result<T, PciError> {
+    let addr = config_address(segment, bus, device, function, offset)?;
 
     let value = unsafe { addr.as_ptr::<T>().read_volatile() };
     Ok(value)
@@ -211,12 +256,7 @@ pub(crate) fn write<T: 'static + Copy>(
     offset: u16,
     value: T,
 ) -> Result<(), PciError> {
-    let pci_config_regions = PCI_CONFIG_REGIONS.get().ok_or(PciError::NotInitialized)?;
-
-    let base_addr = pci_config_regions
-        .physical_address(segment, bus, device, function)
-        .ok_or(PciError::InvalidPciAddress)?;
-    let addr = memory::physical_to_virtual(PhysAddr::new(base_addr + offset as u64));
+    let addr = config_address(segment, bus, device, function, offset)?;
 
     unsafe {
         addr.as_mut_ptr::<T>().write_volatile(value);
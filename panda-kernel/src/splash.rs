@@ -0,0 +1,28 @@
+//! Keeps the loader's boot splash up to date until the display takes over.
+
+use conquer_once::spin::OnceCell;
+use panda_loader_lib::{BootStage, FrameBuffer};
+use spin::Mutex;
+
+use crate::cmdline;
+
+static SPLASH: OnceCell<Mutex<FrameBuffer>> = OnceCell::uninit();
+
+/// Does nothing if the loader didn't draw a splash, or if the display is
+/// turned off.
+pub fn init(frame_buffer: &FrameBuffer, enabled: bool) {
+    if !enabled || cmdline::flag("nodisplay") {
+        return;
+    }
+
+    SPLASH.init_once(|| Mutex::new(frame_buffer.clone()));
+}
+
+/// Moves the progress bar along once `stage` has finished.
+pub fn advance(stage: BootStage) {
+    log::debug!("Boot stage {stage:?} done");
+
+    if let Some(frame_buffer) = SPLASH.get() {
+        panda_loader_lib::draw_progress(&mut frame_buffer.lock(), stage);
+    }
+}
@@ -0,0 +1,39 @@
+use x86_64::PhysAddr;
+
+pub const MAX_BOOT_MODULE_NAME_LENGTH: usize = 64;
+
+/// A file the loader brought into memory for the kernel alongside its own
+/// image, e.g. an initial ramdisk.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule {
+    name: [u8; MAX_BOOT_MODULE_NAME_LENGTH],
+    name_len: usize,
+    pub base_addr: PhysAddr,
+    pub size: u64,
+}
+
+impl BootModule {
+    /// Names longer than `MAX_BOOT_MODULE_NAME_LENGTH` bytes are truncated.
+    pub fn new(name: &str, base_addr: PhysAddr, size: u64) -> Self {
+        let mut name_len = name.len().min(MAX_BOOT_MODULE_NAME_LENGTH);
+        while !name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+
+        let mut buffer = [0; MAX_BOOT_MODULE_NAME_LENGTH];
+        buffer[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+
+        BootModule {
+            name: buffer,
+            name_len,
+            base_addr,
+            size,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let length = self.name_len.min(MAX_BOOT_MODULE_NAME_LENGTH);
+        core::str::from_utf8(&self.name[..length]).unwrap_or("<invalid>")
+    }
+}
@@ -0,0 +1,22 @@
+/// The vendor GUID Panda's UEFI variables are stored under
+/// (390bd34b-2a98-4453-ab44-fd6415b31760), as the bytes of an `EFI_GUID`.
+pub const PANDA_VARIABLE_VENDOR: [u8; 16] = [
+    0x4B, 0xD3, 0x0B, 0x39, 0x98, 0x2A, 0x53, 0x44, 0xAB, 0x44, 0xFD, 0x64, 0x15, 0xB3, 0x17, 0x60,
+];
+
+/// A one byte UEFI variable counting how many times slot A has been booted
+/// without the kernel marking the boot successful.
+pub const BOOT_ATTEMPTS_VARIABLE: &str = "PandaBootAttempts";
+
+/// `NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS`, so the counter
+/// survives a reset and the kernel can still write it.
+pub const BOOT_ATTEMPTS_ATTRIBUTES: u32 = 0x7;
+
+/// Which of the A/B kernel slots was booted, if slots are set up at all.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootSlot {
+    None,
+    A,
+    B,
+}
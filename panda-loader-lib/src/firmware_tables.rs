@@ -0,0 +1,51 @@
+use x86_64::PhysAddr;
+
+/// Where the firmware put its configuration tables. Addresses are physical,
+/// and zero means the firmware didn't provide that table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirmwareTables {
+    pub rsdp_address: u64,
+    // 0 for an ACPI 1.0 RSDP, 2 for ACPI 2.0 and later, as in the RSDP itself
+    pub rsdp_revision: u8,
+    pub smbios_address: u64,
+    pub smbios3_address: u64,
+    pub efi_system_table_address: u64,
+    pub efi_memory_map: EfiMemoryMap,
+}
+
+/// The memory map as the firmware reported it when boot services were
+/// exited, which is needed to call `SetVirtualAddressMap`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EfiMemoryMap {
+    pub address: u64,
+    pub size: usize,
+    pub descriptor_size: usize,
+    pub descriptor_version: u32,
+}
+
+impl FirmwareTables {
+    pub fn rsdp(&self) -> Option<PhysAddr> {
+        optional_address(self.rsdp_address)
+    }
+
+    pub fn smbios(&self) -> Option<PhysAddr> {
+        optional_address(self.smbios_address)
+    }
+
+    pub fn smbios3(&self) -> Option<PhysAddr> {
+        optional_address(self.smbios3_address)
+    }
+
+    pub fn efi_system_table(&self) -> Option<PhysAddr> {
+        optional_address(self.efi_system_table_address)
+    }
+}
+
+fn optional_address(address: u64) -> Option<PhysAddr> {
+    match address {
+        0 => None,
+        address => Some(PhysAddr::new(address)),
+    }
+}
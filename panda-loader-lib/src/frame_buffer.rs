@@ -1,75 +1,120 @@
+/// Which bits of a 32-bit pixel hold each colour channel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PixelBitmask {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub reserved: u32,
+}
+
+impl PixelBitmask {
+    fn encode(&self, (red, green, blue): (u8, u8, u8)) -> u32 {
+        encode_channel(self.red, red)
+            | encode_channel(self.green, green)
+            | encode_channel(self.blue, blue)
+    }
+
+    fn decode(&self, pixel: u32) -> (u8, u8, u8) {
+        (
+            decode_channel(self.red, pixel),
+            decode_channel(self.green, pixel),
+            decode_channel(self.blue, pixel),
+        )
+    }
+}
+
+/// Scales an 8-bit channel value to however many bits the mask has, and
+/// moves it into place.
+fn encode_channel(mask: u32, value: u8) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let bits = mask.count_ones();
+    let value = value as u32;
+    let scaled = if bits >= 8 {
+        value << (bits - 8)
+    } else {
+        value >> (8 - bits)
+    };
+
+    (scaled << mask.trailing_zeros()) & mask
+}
+
+fn decode_channel(mask: u32, pixel: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let bits = mask.count_ones();
+    let value = (pixel & mask) >> mask.trailing_zeros();
+    let scaled = if bits >= 8 {
+        value >> (bits - 8)
+    } else {
+        value << (8 - bits)
+    };
+
+    scaled as u8
+}
+
+#[repr(u32)]
 #[derive(Debug, Clone, Copy)]
 pub enum PixelFormat {
     BGR,
     RGB,
+    Bitmask(PixelBitmask),
 }
 
+#[repr(C)]
 #[derive(Debug, Clone)]
 pub struct FrameBuffer {
     pub base_addr: usize,
-    pub resolution: (usize, usize),
+    pub width: usize,
+    pub height: usize,
+    // in pixels, which can be more than the width
     pub stride: usize,
     pub pixel_format: PixelFormat,
 }
 
 impl FrameBuffer {
-    fn ptr(&self) -> *mut u8 {
-        self.base_addr as *mut u8
+    /// Every pixel format we support is 32 bits wide.
+    fn pixel_ptr(&self, (x, y): (usize, usize)) -> *mut u32 {
+        let index = x + (y * self.stride);
+        unsafe { (self.base_addr as *mut u32).add(index) }
+    }
+
+    pub fn resolution(&self) -> (usize, usize) {
+        (self.width, self.height)
     }
+
     pub fn read_pixel(&self, position: (usize, usize)) -> (u8, u8, u8) {
-        if position.0 >= self.resolution.0 || position.1 >= self.resolution.1 {
+        if position.0 >= self.width || position.1 >= self.height {
             return (0, 0, 0);
         }
 
-        let index = position.0 + (position.1 * self.resolution.0);
-
-        unsafe {
-            match self.pixel_format {
-                PixelFormat::BGR => {
-                    let blue = *self.ptr().add((4 * index) + 0);
-                    let green = *self.ptr().add((4 * index) + 1);
-                    let red = *self.ptr().add((4 * index) + 2);
-
-                    (red, green, blue)
-                }
+        let pixel = unsafe { self.pixel_ptr(position).read_volatile() };
+        let [byte0, byte1, byte2, _] = pixel.to_le_bytes();
 
-                PixelFormat::RGB => {
-                    let red = *self.ptr().add((4 * index) + 0);
-                    let green = *self.ptr().add((4 * index) + 1);
-                    let blue = *self.ptr().add((4 * index) + 2);
-
-                    (red, green, blue)
-                }
-            }
+        match self.pixel_format {
+            PixelFormat::BGR => (byte2, byte1, byte0),
+            PixelFormat::RGB => (byte0, byte1, byte2),
+            PixelFormat::Bitmask(bitmask) => bitmask.decode(pixel),
         }
     }
 
     pub fn draw_pixel(&mut self, position: (usize, usize), colour: (u8, u8, u8)) {
-        if position.0 > self.resolution.0 {
-            return;
-        }
-
-        if position.1 > self.resolution.1 {
+        if position.0 >= self.width || position.1 >= self.height {
             return;
         }
 
         let (red, green, blue) = colour;
-        let index = position.0 + (position.1 * self.resolution.0);
+        let pixel = match self.pixel_format {
+            PixelFormat::BGR => u32::from_le_bytes([blue, green, red, 0]),
+            PixelFormat::RGB => u32::from_le_bytes([red, green, blue, 0]),
+            PixelFormat::Bitmask(bitmask) => bitmask.encode(colour),
+        };
 
-        match self.pixel_format {
-            PixelFormat::BGR => unsafe {
-                *self.ptr().add((4 * index) + 0) = blue;
-                *self.ptr().add((4 * index) + 1) = green;
-                *self.ptr().add((4 * index) + 2) = red;
-                *self.ptr().add((4 * index) + 3) = 0;
-            },
-
-            PixelFormat::RGB => unsafe {
-                *self.ptr().add((4 * index) + 0) = red;
-                *self.ptr().add((4 * index) + 1) = green;
-                *self.ptr().add((4 * index) + 2) = blue;
-                *self.ptr().add((4 * index) + 3) = 0;
-            },
-        }
+        unsafe { self.pixel_ptr(position).write_volatile(pixel) }
     }
 }
@@ -1,54 +1,266 @@
 #![no_std]
 extern crate alloc;
 
+mod boot_module;
+mod boot_slot;
+mod firmware_tables;
 mod frame_buffer;
 mod memory_descriptor;
+mod panic_report;
+mod splash;
+mod symbols;
 
-use alloc::vec::Vec;
-use x86_64::{PhysAddr, VirtAddr};
+use alloc::{string::String, vec::Vec};
+use x86_64::VirtAddr;
 
-pub use frame_buffer::{FrameBuffer, PixelFormat};
+pub use boot_module::BootModule;
+pub use boot_slot::{
+    BootSlot, BOOT_ATTEMPTS_ATTRIBUTES, BOOT_ATTEMPTS_VARIABLE, PANDA_VARIABLE_VENDOR,
+};
+pub use firmware_tables::{EfiMemoryMap, FirmwareTables};
+pub use frame_buffer::{FrameBuffer, PixelBitmask, PixelFormat};
 pub use memory_descriptor::{MemoryDescriptor, MemoryDescriptorType};
+pub use panic_report::{PANIC_REPORT_ATTRIBUTES, PANIC_REPORT_MAX_SIZE, PANIC_REPORT_VARIABLE};
+pub use splash::{draw_progress, draw_splash, BootStage};
+pub use symbols::{Symbol, SymbolTable};
 
 pub type KernelEntryFn = extern "win64" fn(&LoaderCarePackage);
 
 const CARE_PACKAGE_MAGIC_NUMBER: u64 = 0x5542_5542_5542_5542;
 
+/// Bumped whenever the layout of `LoaderCarePackage`, or of anything it
+/// points to, changes.
+pub const CARE_PACKAGE_VERSION: u32 = 11;
+
+/// Whether the loader checked the kernel image's signature. Loaders only
+/// start kernels that failed the check if they were built to allow it.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelVerification {
+    Verified,
+    Unsigned,
+    InvalidSignature,
+}
+
 #[derive(Debug)]
 pub enum LoaderCarePackageError {
     InvalidMagicNumber,
+    VersionMismatch { expected: u32, actual: u32 },
+    SizeMismatch { expected: u32, actual: u32 },
 }
 
+/// Everything the loader hands over to the kernel.
+///
+/// The loader and kernel are built separately, so this only contains
+/// `#[repr(C)]` types and raw arrays rather than anything owned by the
+/// loader's allocator. The arrays are leaked by the loader and stay where
+/// they are for as long as the kernel wants them.
+///
+/// Pointers (including the frame buffer's) are addresses in the kernel's
+/// direct map of physical memory, or in the kernel image itself, so they
+/// stay valid after the kernel drops the loader's identity map.
+#[repr(C)]
 #[derive(Debug)]
 pub struct LoaderCarePackage {
     magic_number: u64,
-    pub frame_buffer: FrameBuffer,
-    pub memory_map: Vec<MemoryDescriptor>,
-    pub phys_memory_virt_offset: VirtAddr,
-    pub rsdp_address: Option<PhysAddr>,
+    version: u32,
+    size: u32,
+    frame_buffer: FrameBuffer,
+    memory_map: *const MemoryDescriptor,
+    memory_map_len: usize,
+    phys_memory_virt_offset: u64,
+    firmware_tables: FirmwareTables,
+    command_line: *const u8,
+    command_line_len: usize,
+    modules: *const BootModule,
+    modules_len: usize,
+    symbols: *const Symbol,
+    symbols_len: usize,
+    symbol_names: *const u8,
+    symbol_names_len: usize,
+    kernel_slide: u64,
+    kernel_verification: KernelVerification,
+    boot_slot: BootSlot,
+    splash: bool,
 }
 
 impl LoaderCarePackage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         frame_buffer: FrameBuffer,
         memory_map: Vec<MemoryDescriptor>,
         phys_memory_virt_offset: VirtAddr,
-        rsdp_address: Option<PhysAddr>,
+        firmware_tables: FirmwareTables,
+        command_line: String,
+        modules: Vec<BootModule>,
+        symbols: SymbolTable<'static>,
+        kernel_slide: u64,
+        kernel_verification: KernelVerification,
+        boot_slot: BootSlot,
+        splash: bool,
+    ) -> Self {
+        let mut care_package = Self::from_static(
+            frame_buffer,
+            memory_map.leak(),
+            phys_memory_virt_offset,
+            firmware_tables,
+            command_line.leak(),
+            modules.leak(),
+            symbols,
+            kernel_slide,
+            kernel_verification,
+            boot_slot,
+            splash,
+        );
+
+        // the loader runs identity mapped, so its addresses are physical ones
+        let offset = phys_memory_virt_offset.as_u64();
+        let to_direct_map = |ptr: *const u8| (ptr as u64 + offset) as *const u8;
+
+        care_package.frame_buffer.base_addr += offset as usize;
+        care_package.memory_map = to_direct_map(care_package.memory_map.cast()).cast();
+        care_package.command_line = to_direct_map(care_package.command_line);
+        care_package.modules = to_direct_map(care_package.modules.cast()).cast();
+        care_package.symbols = to_direct_map(care_package.symbols.cast()).cast();
+        care_package.symbol_names = to_direct_map(care_package.symbol_names);
+
+        care_package
+    }
+
+    /// Like `new`, but for arrays that are already somewhere the kernel can
+    /// reach for good, e.g. in its own image, and a frame buffer whose
+    /// address is already a virtual one. Nothing is leaked or moved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_static(
+        frame_buffer: FrameBuffer,
+        memory_map: &'static [MemoryDescriptor],
+        phys_memory_virt_offset: VirtAddr,
+        firmware_tables: FirmwareTables,
+        command_line: &'static str,
+        modules: &'static [BootModule],
+        symbols: SymbolTable<'static>,
+        kernel_slide: u64,
+        kernel_verification: KernelVerification,
+        boot_slot: BootSlot,
+        splash: bool,
     ) -> Self {
         LoaderCarePackage {
             magic_number: CARE_PACKAGE_MAGIC_NUMBER,
+            version: CARE_PACKAGE_VERSION,
+            size: core::mem::size_of::<Self>() as u32,
             frame_buffer,
-            memory_map,
-            phys_memory_virt_offset,
-            rsdp_address,
+            memory_map: memory_map.as_ptr(),
+            memory_map_len: memory_map.len(),
+            phys_memory_virt_offset: phys_memory_virt_offset.as_u64(),
+            firmware_tables,
+            command_line: command_line.as_ptr(),
+            command_line_len: command_line.len(),
+            modules: modules.as_ptr(),
+            modules_len: modules.len(),
+            symbols: symbols.symbols().as_ptr(),
+            symbols_len: symbols.len(),
+            symbol_names: symbols.names().as_ptr(),
+            symbol_names_len: symbols.names().len(),
+            kernel_slide,
+            kernel_verification,
+            boot_slot,
+            splash,
         }
     }
 
+    /// Checks that the care package was built by a loader that agrees with
+    /// us on its layout. None of the other methods are safe to call if this
+    /// fails.
     pub fn validate(&self) -> Result<(), LoaderCarePackageError> {
         if self.magic_number != CARE_PACKAGE_MAGIC_NUMBER {
             return Err(LoaderCarePackageError::InvalidMagicNumber);
         }
 
+        if self.version != CARE_PACKAGE_VERSION {
+            return Err(LoaderCarePackageError::VersionMismatch {
+                expected: CARE_PACKAGE_VERSION,
+                actual: self.version,
+            });
+        }
+
+        let expected_size = core::mem::size_of::<Self>() as u32;
+        if self.size != expected_size {
+            return Err(LoaderCarePackageError::SizeMismatch {
+                expected: expected_size,
+                actual: self.size,
+            });
+        }
+
         Ok(())
     }
+
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.frame_buffer
+    }
+
+    pub fn memory_map(&self) -> &[MemoryDescriptor] {
+        unsafe { slice_from_raw_parts(self.memory_map, self.memory_map_len) }
+    }
+
+    pub fn phys_memory_virt_offset(&self) -> VirtAddr {
+        VirtAddr::new(self.phys_memory_virt_offset)
+    }
+
+    pub fn firmware_tables(&self) -> &FirmwareTables {
+        &self.firmware_tables
+    }
+
+    pub fn command_line(&self) -> &str {
+        unsafe {
+            let bytes = slice_from_raw_parts(self.command_line, self.command_line_len);
+            core::str::from_utf8_unchecked(bytes)
+        }
+    }
+
+    pub fn modules(&self) -> &[BootModule] {
+        unsafe { slice_from_raw_parts(self.modules, self.modules_len) }
+    }
+
+    /// How far the kernel was moved from the address it was linked at. The
+    /// symbol table has link-time addresses.
+    pub fn kernel_slide(&self) -> u64 {
+        self.kernel_slide
+    }
+
+    pub fn kernel_verification(&self) -> KernelVerification {
+        self.kernel_verification
+    }
+
+    pub fn boot_slot(&self) -> BootSlot {
+        self.boot_slot
+    }
+
+    /// Whether the loader drew the boot splash, which the kernel should then
+    /// keep up to date until its own display takes over.
+    pub fn splash(&self) -> bool {
+        self.splash
+    }
+
+    /// The table outlives the care package: the loader puts it in pages of
+    /// the kernel's memory type, and on the Multiboot path it's part of the
+    /// kernel image, and neither is ever reclaimed.
+    pub fn symbols(&self) -> SymbolTable<'static> {
+        // SAFETY: see above, the arrays are never freed or moved
+        unsafe {
+            SymbolTable::new(
+                slice_from_raw_parts(self.symbols, self.symbols_len),
+                slice_from_raw_parts(self.symbol_names, self.symbol_names_len),
+            )
+        }
+    }
+}
+
+/// Like `core::slice::from_raw_parts`, but allows a null pointer for an empty
+/// array.
+unsafe fn slice_from_raw_parts<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        &[]
+    } else {
+        core::slice::from_raw_parts(ptr, len)
+    }
 }
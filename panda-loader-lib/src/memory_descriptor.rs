@@ -1,12 +1,14 @@
 use x86_64::PhysAddr;
 
-#[derive(Debug)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
 pub struct MemoryDescriptor {
     pub base_addr: PhysAddr,
     pub length: u64,
     pub memory_type: MemoryDescriptorType,
 }
 
+#[repr(u32)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MemoryDescriptorType {
     // the memory is available for use by the kernel
@@ -17,4 +19,36 @@ pub enum MemoryDescriptorType {
 
     // the memory may be used once data has been read from the ACPI tables
     AcpiReclaimable,
+
+    // the firmware keeps ACPI state here, so it must never be used
+    AcpiNvs,
+
+    // device registers rather than memory
+    Mmio,
+
+    // the firmware's runtime services live here, and must stay mapped to
+    // call them
+    RuntimeServicesCode,
+    RuntimeServicesData,
+
+    // used by the firmware before boot services were exited, and free once
+    // nothing the loader handed over lives there any more
+    BootServicesReclaimable,
+
+    // the kernel image, its initial page tables and its stack
+    LoaderKernel,
+
+    // files the loader loaded for the kernel
+    LoaderModules,
+
+    // non-volatile memory, which keeps its contents across reboots
+    Persistent,
+
+    // the firmware found errors in this memory
+    Unusable,
+
+    // the loader's own code and data, including everything the care package
+    // points to outside the kernel image, so free once the kernel has copied
+    // what it needs
+    LoaderReclaimable,
 }
@@ -0,0 +1,10 @@
+/// A UEFI variable the kernel saves the end of its console output in when it
+/// panics, which takes in the panic message and backtrace. The loader shows
+/// it on the next boot and then deletes it.
+pub const PANIC_REPORT_VARIABLE: &str = "PandaPanicReport";
+
+/// `NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS`.
+pub const PANIC_REPORT_ATTRIBUTES: u32 = 0x7;
+
+/// Firmware often won't store variables much bigger than a few kilobytes.
+pub const PANIC_REPORT_MAX_SIZE: usize = 4096;
@@ -0,0 +1,229 @@
+//! The boot splash: a logo in the middle of the screen with a progress bar
+//! underneath. The loader draws it and the kernel keeps the bar moving, so
+//! that how far the bar got shows where a hung boot got stuck.
+
+use core::convert::TryInto;
+
+use crate::FrameBuffer;
+
+/// The logo, in the Quite OK Image format (https://qoiformat.org).
+const LOGO: &[u8] = include_bytes!("../images/logo.qoi");
+
+const BAR_WIDTH: usize = 240;
+const BAR_HEIGHT: usize = 6;
+// between the bottom of the logo and the top of the bar
+const BAR_GAP: usize = 32;
+
+const BAR_EMPTY_COLOUR: (u8, u8, u8) = (48, 48, 48);
+const BAR_FULL_COLOUR: (u8, u8, u8) = (245, 245, 240);
+
+/// The steps a boot goes through, in order. Each one moves the progress bar
+/// along once it's finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BootStage {
+    // in the loader
+    KernelLoaded,
+    ModulesLoaded,
+    BootServicesExited,
+
+    // in the kernel
+    MemoryReady,
+    FirmwareReady,
+    DisplayReady,
+    TasksReady,
+    AcpiReady,
+    Done,
+}
+
+impl BootStage {
+    const COUNT: usize = BootStage::Done as usize + 1;
+}
+
+/// Draws the logo and an empty progress bar.
+pub fn draw_splash(frame_buffer: &mut FrameBuffer) {
+    let Some(logo) = QoiImage::parse(LOGO) else {
+        return;
+    };
+
+    let (logo_x, logo_y) = logo_position(frame_buffer, &logo);
+
+    for (index, [red, green, blue, alpha]) in logo.pixels().enumerate() {
+        // the logo is drawn over a black screen, so blending is just scaling
+        let blend = |channel: u8| (channel as u16 * alpha as u16 / 255) as u8;
+
+        if alpha != 0 {
+            let position = (logo_x + index % logo.width, logo_y + index / logo.width);
+            frame_buffer.draw_pixel(position, (blend(red), blend(green), blend(blue)));
+        }
+    }
+
+    draw_bar(frame_buffer, 0);
+}
+
+/// Fills in the progress bar up to the end of `stage`.
+pub fn draw_progress(frame_buffer: &mut FrameBuffer, stage: BootStage) {
+    let filled = BAR_WIDTH * (stage as usize + 1) / BootStage::COUNT;
+    draw_bar(frame_buffer, filled);
+}
+
+fn logo_position(frame_buffer: &FrameBuffer, logo: &QoiImage) -> (usize, usize) {
+    let x = frame_buffer.width.saturating_sub(logo.width) / 2;
+    let y = frame_buffer
+        .height
+        .saturating_sub(logo.height + BAR_GAP + BAR_HEIGHT)
+        / 2;
+
+    (x, y)
+}
+
+fn draw_bar(frame_buffer: &mut FrameBuffer, filled: usize) {
+    let Some(logo) = QoiImage::parse(LOGO) else {
+        return;
+    };
+
+    let (_, logo_y) = logo_position(frame_buffer, &logo);
+    let bar_x = frame_buffer.width.saturating_sub(BAR_WIDTH) / 2;
+    let bar_y = logo_y + logo.height + BAR_GAP;
+
+    for y in bar_y..bar_y + BAR_HEIGHT {
+        for x in 0..BAR_WIDTH {
+            let colour = if x < filled {
+                BAR_FULL_COLOUR
+            } else {
+                BAR_EMPTY_COLOUR
+            };
+
+            frame_buffer.draw_pixel((bar_x + x, y), colour);
+        }
+    }
+}
+
+/// Just enough of a QOI decoder to draw the logo, without allocating.
+struct QoiImage<'a> {
+    width: usize,
+    height: usize,
+    data: &'a [u8],
+}
+
+impl<'a> QoiImage<'a> {
+    const HEADER_SIZE: usize = 14;
+
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < Self::HEADER_SIZE || &data[0..4] != b"qoif" {
+            return None;
+        }
+
+        let width = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+
+        Some(QoiImage {
+            width,
+            height,
+            data: &data[Self::HEADER_SIZE..],
+        })
+    }
+
+    /// The image's RGBA pixels, row by row.
+    fn pixels(&self) -> QoiPixels<'a> {
+        QoiPixels {
+            data: self.data,
+            remaining: self.width * self.height,
+            index: [[0; 4]; 64],
+            previous: [0, 0, 0, 255],
+            run: 0,
+        }
+    }
+}
+
+struct QoiPixels<'a> {
+    data: &'a [u8],
+    remaining: usize,
+    index: [[u8; 4]; 64],
+    previous: [u8; 4],
+    run: usize,
+}
+
+impl QoiPixels<'_> {
+    fn next_byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(byte)
+    }
+}
+
+impl Iterator for QoiPixels<'_> {
+    type Item = [u8; 4];
+
+    fn next(&mut self) -> Option<[u8; 4]> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+
+        if self.run > 0 {
+            self.run -= 1;
+            return Some(self.previous);
+        }
+
+        let [red, green, blue, alpha] = self.previous;
+        let tag = self.next_byte()?;
+
+        let pixel = match tag {
+            0xFE => [
+                self.next_byte()?,
+                self.next_byte()?,
+                self.next_byte()?,
+                alpha,
+            ],
+            0xFF => [
+                self.next_byte()?,
+                self.next_byte()?,
+                self.next_byte()?,
+                self.next_byte()?,
+            ],
+
+            _ => match tag >> 6 {
+                // a pixel seen before
+                0b00 => self.index[tag as usize],
+
+                // a small difference from the previous pixel
+                0b01 => {
+                    let diff = |shift: u8| ((tag >> shift) & 0b11).wrapping_sub(2);
+                    [
+                        red.wrapping_add(diff(4)),
+                        green.wrapping_add(diff(2)),
+                        blue.wrapping_add(diff(0)),
+                        alpha,
+                    ]
+                }
+
+                // a bigger difference, relative to the change in green
+                0b10 => {
+                    let byte = self.next_byte()?;
+                    let green_diff = (tag & 0b11_1111).wrapping_sub(32);
+                    let red_diff = green_diff.wrapping_add(byte >> 4).wrapping_sub(8);
+                    let blue_diff = green_diff.wrapping_add(byte & 0b1111).wrapping_sub(8);
+                    [
+                        red.wrapping_add(red_diff),
+                        green.wrapping_add(green_diff),
+                        blue.wrapping_add(blue_diff),
+                        alpha,
+                    ]
+                }
+
+                // the previous pixel repeated, this time included
+                _ => {
+                    self.run = (tag & 0b11_1111) as usize;
+                    self.previous
+                }
+            },
+        };
+
+        let [r, g, b, a] = pixel.map(|channel| channel as usize);
+        self.index[(r * 3 + g * 5 + b * 7 + a * 11) % 64] = pixel;
+        self.previous = pixel;
+
+        Some(pixel)
+    }
+}
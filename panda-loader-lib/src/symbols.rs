@@ -0,0 +1,76 @@
+/// A function in the kernel image. The name is stored separately, in the
+/// symbol table's block of names.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub address: u64,
+    pub size: u64,
+    pub name_offset: u32,
+    pub name_len: u32,
+}
+
+/// The kernel's function symbols, sorted by address, so that addresses in a
+/// backtrace can be turned back into names.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTable<'a> {
+    symbols: &'a [Symbol],
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// `symbols` must be sorted by address.
+    pub fn new(symbols: &'a [Symbol], names: &'a [u8]) -> Self {
+        SymbolTable { symbols, names }
+    }
+
+    pub fn empty() -> Self {
+        SymbolTable {
+            symbols: &[],
+            names: &[],
+        }
+    }
+
+    pub fn symbols(&self) -> &'a [Symbol] {
+        self.symbols
+    }
+
+    pub fn names(&self) -> &'a [u8] {
+        self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn name(&self, symbol: &Symbol) -> &'a str {
+        let start = symbol.name_offset as usize;
+        let end = start + symbol.name_len as usize;
+
+        self.names
+            .get(start..end)
+            .and_then(|name| core::str::from_utf8(name).ok())
+            .unwrap_or("<invalid>")
+    }
+
+    /// Finds the function containing `address`, and how far into it the
+    /// address is.
+    pub fn lookup(&self, address: u64) -> Option<(&'a str, u64)> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols.get(index.checked_sub(1)?)?;
+        let offset = address - symbol.address;
+
+        // some symbols have no size, so just assume those cover everything
+        // up to the next one
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+
+        Some((self.name(symbol), offset))
+    }
+}
@@ -0,0 +1,16 @@
+use std::path::Path;
+
+// the loader embeds the public half of the kernel signing key, which isn't
+// checked in, so say how to make one rather than failing in include_bytes!
+const PUBLIC_KEY: &str = "keys/kernel-signing.pub";
+
+fn main() {
+    println!("cargo:rerun-if-changed={PUBLIC_KEY}");
+
+    if !Path::new(PUBLIC_KEY).exists() {
+        panic!(
+            "panda-loader/{PUBLIC_KEY} is missing. Run `make keys` from the top of the \
+             repository to generate a kernel signing key."
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use log::LevelFilter;
+
+pub const CONFIG_PATH: &str = "\\EFI\\panda.cfg";
+pub const DEFAULT_KERNEL_PATH: &str = "\\EFI\\kernel.elf";
+
+/// Settings read from `\EFI\panda.cfg`. The file is made of `key = value`
+/// lines; blank lines and anything after a `#` are ignored.
+///
+/// ```text
+/// log_level = info
+/// video_mode = 1024x768
+/// kaslr = on
+/// splash = on
+/// default = release
+/// timeout = 5
+///
+/// [release]
+/// title = Release build
+/// kernel = \EFI\kernel.elf
+/// cmdline = log=info
+/// module = \EFI\initrd.tar
+///
+/// [debug]
+/// kernel = \EFI\kernel-debug.elf.zst
+/// cmdline = log=debug
+/// ```
+///
+/// Each `[name]` section is an entry in the boot menu. `kernel`, `cmdline`
+/// and `module` set before the first section make up an entry called
+/// `default`, so a config without any sections boots that. `module` can be
+/// given more than once to load several files. The kernel may be compressed
+/// with gzip, LZ4 or zstd.
+///
+/// `splash` shows a logo and progress bar instead of log messages while
+/// booting.
+///
+/// `timeout` is how many seconds the menu waits before booting the default
+/// entry; `0` boots it straight away and `none` waits for a key press.
+///
+/// For unattended machines, `slot_a = <entry>` and `slot_b = <entry>` set up
+/// A/B slots. Slot A is the default until it's been booted `boot_attempts`
+/// times (3 unless set) without the kernel marking the boot successful,
+/// after which slot B is.
+#[derive(Debug, Clone)]
+pub struct BootConfig {
+    pub log_level: LevelFilter,
+    pub video_mode: Option<(usize, usize)>,
+    pub kaslr: bool,
+    pub splash: bool,
+    pub default_entry: Option<String>,
+    pub timeout: Option<u64>,
+    pub slot_a: Option<String>,
+    pub slot_b: Option<String>,
+    pub boot_attempts: u8,
+    pub entries: Vec<BootEntry>,
+}
+
+/// A kernel that can be picked from the boot menu.
+#[derive(Debug, Clone)]
+pub struct BootEntry {
+    pub name: String,
+    pub title: Option<String>,
+    pub kernel_path: String,
+    pub command_line: String,
+    pub modules: Vec<String>,
+}
+
+impl BootEntry {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            title: None,
+            kernel_path: DEFAULT_KERNEL_PATH.to_string(),
+            command_line: String::new(),
+            modules: Vec::new(),
+        }
+    }
+
+    /// What the entry is called in the boot menu.
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            log_level: LevelFilter::Info,
+            video_mode: None,
+            kaslr: true,
+            splash: true,
+            default_entry: None,
+            timeout: Some(5),
+            slot_a: None,
+            slot_b: None,
+            boot_attempts: 3,
+            entries: vec![BootEntry::new("default")],
+        }
+    }
+}
+
+impl BootConfig {
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self {
+            entries: Vec::new(),
+            ..Self::default()
+        };
+
+        let mut top_level_entry = BootEntry::new("default");
+        let mut top_level_kernel = false;
+        let mut section: Option<BootEntry> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = match line.split_once('#') {
+                Some((line, _comment)) => line,
+                None => line,
+            }
+            .trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                config.entries.extend(section.take());
+                section = Some(BootEntry::new(name.trim()));
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                log::warn!("{CONFIG_PATH}:{}: expected `key = value`", number + 1);
+                continue;
+            };
+
+            let (key, value) = (key.trim(), value.trim());
+            let in_section = section.is_some();
+            let entry = section.as_mut().unwrap_or(&mut top_level_entry);
+
+            match key {
+                "title" => entry.title = Some(value.to_string()),
+                "kernel" => {
+                    entry.kernel_path = value.to_string();
+                    top_level_kernel |= !in_section;
+                }
+                "cmdline" => entry.command_line = value.to_string(),
+                "module" => entry.modules.push(value.to_string()),
+                _ => {
+                    if in_section {
+                        log::warn!(
+                            "{CONFIG_PATH}:{}: {key} applies to every entry, not just this one",
+                            number + 1
+                        );
+                    }
+
+                    config.set_global(key, value);
+                }
+            }
+        }
+
+        config.entries.extend(section);
+
+        if top_level_kernel || config.entries.is_empty() {
+            config.entries.insert(0, top_level_entry);
+        }
+
+        config
+    }
+
+    fn set_global(&mut self, key: &str, value: &str) {
+        match key {
+            "log_level" => match value.parse() {
+                Ok(level) => self.log_level = level,
+                Err(_) => log::warn!("{CONFIG_PATH}: invalid log level {value:?}"),
+            },
+            "video_mode" => match parse_resolution(value) {
+                Some(resolution) => self.video_mode = Some(resolution),
+                None => log::warn!("{CONFIG_PATH}: invalid video mode {value:?}"),
+            },
+            "kaslr" => match parse_bool(value) {
+                Some(kaslr) => self.kaslr = kaslr,
+                None => log::warn!("{CONFIG_PATH}: invalid value for kaslr {value:?}"),
+            },
+            "splash" => match parse_bool(value) {
+                Some(splash) => self.splash = splash,
+                None => log::warn!("{CONFIG_PATH}: invalid value for splash {value:?}"),
+            },
+            "default" => self.default_entry = Some(value.to_string()),
+            "timeout" => match value {
+                "none" => self.timeout = None,
+                _ => match value.parse() {
+                    Ok(seconds) => self.timeout = Some(seconds),
+                    Err(_) => log::warn!("{CONFIG_PATH}: invalid timeout {value:?}"),
+                },
+            },
+            "slot_a" => self.slot_a = Some(value.to_string()),
+            "slot_b" => self.slot_b = Some(value.to_string()),
+            "boot_attempts" => match value.parse() {
+                Ok(attempts) if attempts > 0 => self.boot_attempts = attempts,
+                _ => log::warn!("{CONFIG_PATH}: invalid number of boot attempts {value:?}"),
+            },
+            _ => log::warn!("{CONFIG_PATH}: unknown setting {key:?}"),
+        }
+    }
+
+    /// The index of the entry to boot if nobody picks one, which is the
+    /// first one unless `default` says otherwise.
+    pub fn default_entry_index(&self) -> usize {
+        let Some(name) = &self.default_entry else {
+            return 0;
+        };
+
+        match self.entries.iter().position(|entry| entry.name == *name) {
+            Some(index) => index,
+            None => {
+                log::warn!("{CONFIG_PATH}: default entry {name:?} doesn't exist");
+                0
+            }
+        }
+    }
+}
+
+/// Parses a resolution like `1024x768`.
+fn parse_resolution(value: &str) -> Option<(usize, usize)> {
+    let (width, height) = value.split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "on" | "yes" | "true" => Some(true),
+        "0" | "off" | "no" | "false" => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,321 @@
+use core::pin::Pin;
+
+use crate::files::page_aligned_buffer;
+
+const GZIP_MAGIC: [u8; 3] = [0x1F, 0x8B, 0x08];
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4D, 0x18];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// Recognises a compressed file by its magic bytes, rather than trusting
+    /// the file name.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if data.starts_with(&LZ4_MAGIC) {
+            Some(Self::Lz4)
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecompressError {
+    Truncated,
+    UnsupportedHeader(&'static str),
+    UnknownSize,
+    InvalidSize(usize),
+    Gzip(miniz_oxide::inflate::TINFLStatus),
+    Lz4(lz4_flex::block::DecompressError),
+    Zstd(ruzstd::frame_decoder::FrameDecoderError),
+    SizeMismatch { expected: usize, actual: usize },
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+/// Decompresses `data` into a new page-aligned buffer if it's gzip, LZ4 or
+/// zstd compressed. Anything else is handed back untouched.
+pub fn decompress(data: Pin<&'static mut [u8]>) -> Result<Pin<&'static mut [u8]>, DecompressError> {
+    let Some(compression) = Compression::detect(&data) else {
+        return Ok(data);
+    };
+
+    let output = match compression {
+        Compression::Gzip => gunzip(&data)?,
+        Compression::Lz4 => unlz4(&data)?,
+        Compression::Zstd => unzstd(&data)?,
+    };
+
+    log::debug!(
+        "Decompressed {compression:?} image from {} to {} bytes",
+        data.len(),
+        output.len()
+    );
+
+    Ok(output)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, DecompressError> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or(DecompressError::Truncated)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, DecompressError> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or(DecompressError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// A gzip member (RFC 1952): a header, raw deflate data, then the CRC-32 and
+/// size of the original data. Only the first member is read.
+fn gunzip(data: &[u8]) -> Result<Pin<&'static mut [u8]>, DecompressError> {
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    let flags = *data.get(3).ok_or(DecompressError::Truncated)?;
+    let mut offset = 10;
+
+    if flags & FEXTRA != 0 {
+        offset += 2 + read_u16(data, offset)? as usize;
+    }
+
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = data.get(offset..).ok_or(DecompressError::Truncated)?;
+            let terminator = rest
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or(DecompressError::Truncated)?;
+            offset += terminator + 1;
+        }
+    }
+
+    if flags & FHCRC != 0 {
+        offset += 2;
+    }
+
+    if data.len() < offset + 8 {
+        return Err(DecompressError::Truncated);
+    }
+
+    // raw deflate has no checksum of its own, so the CRC-32 is all there is
+    // to catch a corrupted image that wasn't signed
+    let deflate = &data[offset..data.len() - 8];
+    let crc = read_u32(data, data.len() - 8)?;
+    let size = read_u32(data, data.len() - 4)? as usize;
+
+    let mut output = page_aligned_buffer(size).ok_or(DecompressError::InvalidSize(size))?;
+    let written = miniz_oxide::inflate::decompress_slice_iter_to_slice(
+        &mut output,
+        core::iter::once(deflate),
+        false,
+        true,
+    )
+    .map_err(DecompressError::Gzip)?;
+
+    if written != size {
+        return Err(DecompressError::SizeMismatch {
+            expected: size,
+            actual: written,
+        });
+    }
+
+    let actual = crc32(&output);
+    if actual != crc {
+        return Err(DecompressError::ChecksumMismatch {
+            expected: crc,
+            actual,
+        });
+    }
+
+    Ok(output)
+}
+
+/// The CRC-32 gzip uses, with the reflected IEEE polynomial.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut index = 0;
+
+        while index < 256 {
+            let mut crc = index as u32;
+            let mut bit = 0;
+
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    0xEDB8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+
+            table[index] = crc;
+            index += 1;
+        }
+
+        table
+    };
+
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// An LZ4 frame. The block decoder has no idea about frames, so the frame
+/// header and block boundaries are handled here.
+fn unlz4(data: &[u8]) -> Result<Pin<&'static mut [u8]>, DecompressError> {
+    const VERSION_MASK: u8 = 0b1100_0000;
+    const VERSION_1: u8 = 0b0100_0000;
+    const BLOCK_INDEPENDENCE: u8 = 1 << 5;
+    const BLOCK_CHECKSUM: u8 = 1 << 4;
+    const CONTENT_SIZE: u8 = 1 << 3;
+    const DICTIONARY_ID: u8 = 1 << 0;
+    const UNCOMPRESSED_BLOCK: u32 = 1 << 31;
+    const WINDOW_SIZE: usize = 64 * 1024;
+
+    let flags = *data.get(4).ok_or(DecompressError::Truncated)?;
+    let block_descriptor = *data.get(5).ok_or(DecompressError::Truncated)?;
+
+    if flags & VERSION_MASK != VERSION_1 {
+        return Err(DecompressError::UnsupportedHeader(
+            "unknown LZ4 frame version",
+        ));
+    }
+
+    if flags & DICTIONARY_ID != 0 {
+        return Err(DecompressError::UnsupportedHeader("LZ4 dictionaries"));
+    }
+
+    let max_block_size = match (block_descriptor >> 4) & 0b111 {
+        4 => 64 * 1024,
+        5 => 256 * 1024,
+        6 => 1024 * 1024,
+        7 => 4 * 1024 * 1024,
+        _ => return Err(DecompressError::UnsupportedHeader("unknown LZ4 block size")),
+    };
+
+    let content_size = if flags & CONTENT_SIZE != 0 {
+        let bytes = data.get(6..14).ok_or(DecompressError::Truncated)?;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    } else {
+        None
+    };
+
+    // magic, flags, block descriptor, content size, header checksum
+    let blocks_start = 7 + if content_size.is_some() { 8 } else { 0 };
+    let block_checksum_size = if flags & BLOCK_CHECKSUM != 0 { 4 } else { 0 };
+
+    // the frame doesn't have to say how big the content is, in which case
+    // every block is assumed to be full
+    let blocks = || {
+        let mut offset = blocks_start;
+        core::iter::from_fn(move || {
+            let header = match read_u32(data, offset) {
+                Ok(0) => return None,
+                Ok(header) => header,
+                Err(error) => return Some(Err(error)),
+            };
+
+            let size = (header & !UNCOMPRESSED_BLOCK) as usize;
+            let Some(block) = data.get(offset + 4..offset + 4 + size) else {
+                return Some(Err(DecompressError::Truncated));
+            };
+
+            offset += 4 + size + block_checksum_size;
+            Some(Ok((block, header & UNCOMPRESSED_BLOCK != 0)))
+        })
+    };
+
+    let output_size = match content_size {
+        Some(size) => size,
+        None => blocks().try_fold(0, |total, block| {
+            let (block, uncompressed) = block?;
+            let size = if uncompressed {
+                block.len()
+            } else {
+                max_block_size
+            };
+            Ok(total + size)
+        })?,
+    };
+
+    let mut output =
+        page_aligned_buffer(output_size).ok_or(DecompressError::InvalidSize(output_size))?;
+    let mut position = 0;
+
+    for block in blocks() {
+        let (block, uncompressed) = block?;
+        let (previous, rest) = output.split_at_mut(position);
+
+        let written = if uncompressed {
+            rest.get_mut(..block.len())
+                .ok_or(DecompressError::SizeMismatch {
+                    expected: output_size,
+                    actual: position + block.len(),
+                })?
+                .copy_from_slice(block);
+            block.len()
+        } else if flags & BLOCK_INDEPENDENCE != 0 {
+            lz4_flex::block::decompress_into(block, rest).map_err(DecompressError::Lz4)?
+        } else {
+            // linked blocks can refer back into the previous 64 KiB
+            let dictionary = &previous[position.saturating_sub(WINDOW_SIZE)..];
+            lz4_flex::block::decompress_into_with_dict(block, rest, dictionary)
+                .map_err(DecompressError::Lz4)?
+        };
+
+        position += written;
+    }
+
+    if content_size.is_some_and(|size| size != position) {
+        return Err(DecompressError::SizeMismatch {
+            expected: output_size,
+            actual: position,
+        });
+    }
+
+    // the buffer may have been sized generously, so trim off what's unused
+    let output = Pin::into_inner(output);
+    Ok(Pin::static_mut(&mut output[..position]))
+}
+
+/// A zstd frame. The frame has to say how big its content is (which `zstd`
+/// does when compressing a file) so the output can be allocated up front.
+fn unzstd(data: &[u8]) -> Result<Pin<&'static mut [u8]>, DecompressError> {
+    let mut decoder = ruzstd::FrameDecoder::new();
+    decoder.init(data).map_err(DecompressError::Zstd)?;
+
+    let size = match decoder.content_size() {
+        0 => return Err(DecompressError::UnknownSize),
+        size => size as usize,
+    };
+
+    let mut output = page_aligned_buffer(size).ok_or(DecompressError::InvalidSize(size))?;
+    let written = decoder
+        .decode_all(data, &mut output)
+        .map_err(DecompressError::Zstd)?;
+
+    if written != size {
+        return Err(DecompressError::SizeMismatch {
+            expected: size,
+            actual: written,
+        });
+    }
+
+    Ok(output)
+}
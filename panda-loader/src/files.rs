@@ -0,0 +1,194 @@
+use core::{alloc::Layout, pin::Pin};
+
+use alloc::vec;
+use uefi::{
+    proto::{
+        loaded_image::LoadedImage,
+        media::{
+            file::{
+                Directory, File, FileAttribute, FileInfo, FileMode, FileSystemInfo, FileType,
+                RegularFile,
+            },
+            fs::SimpleFileSystem,
+        },
+    },
+    table::boot::{AllocateType, BootServices, MemoryType},
+    CStr16, Handle, Status,
+};
+use x86_64::{
+    structures::paging::{PageSize, Size4KiB},
+    PhysAddr,
+};
+
+use crate::config::{CONFIG_PATH, DEFAULT_KERNEL_PATH};
+
+/// Opens the volume the loader was loaded from, so that the kernel comes
+/// from the same disk as the loader. If that doesn't work (say the loader
+/// came over the network), the first volume with a kernel or a boot config
+/// on it is used instead.
+pub fn open_boot_volume(
+    boot_services: &BootServices,
+    image: Handle,
+) -> Result<Directory, uefi::Error> {
+    let loaded_image = boot_services.handle_protocol::<LoadedImage>(image)?;
+    let device = unsafe { &*loaded_image.get() }.device();
+
+    match open_volume(boot_services, device) {
+        Ok(mut volume) => {
+            log_volume("the loader's own device", device, &mut volume);
+            return Ok(volume);
+        }
+
+        Err(error) => log::warn!(
+            "Could not open the loader's own device {device:?}: {:?}",
+            error.status()
+        ),
+    }
+
+    for device in boot_services.find_handles::<SimpleFileSystem>()? {
+        let Ok(mut volume) = open_volume(boot_services, device) else {
+            continue;
+        };
+
+        if file_exists(&mut volume, DEFAULT_KERNEL_PATH) || file_exists(&mut volume, CONFIG_PATH) {
+            log_volume("the first device with a kernel on it", device, &mut volume);
+            return Ok(volume);
+        }
+    }
+
+    log::error!("No device has {DEFAULT_KERNEL_PATH} or {CONFIG_PATH} on it");
+    Err(Status::NOT_FOUND.into())
+}
+
+fn open_volume(boot_services: &BootServices, device: Handle) -> Result<Directory, uefi::Error> {
+    let fs = boot_services.handle_protocol::<SimpleFileSystem>(device)?;
+    let fs = unsafe { &mut *fs.get() };
+    fs.open_volume()
+}
+
+fn log_volume(reason: &str, device: Handle, volume: &mut Directory) {
+    let mut info_buf = vec![0; 512];
+
+    match volume.get_info::<FileSystemInfo>(&mut info_buf) {
+        Ok(info) => log::info!(
+            "Loading from {reason}: {device:?}, volume \"{}\"",
+            info.volume_label()
+        ),
+        Err(_) => log::info!("Loading from {reason}: {device:?}"),
+    }
+}
+
+fn file_exists(volume: &mut Directory, path: &str) -> bool {
+    let mut buf = [0u16; 1024];
+    let Ok(path) = CStr16::from_str_with_buf(path, &mut buf) else {
+        return false;
+    };
+
+    volume
+        .open(path, FileMode::Read, FileAttribute::empty())
+        .is_ok()
+}
+
+fn open_file(volume: &mut Directory, path: &str) -> Result<(RegularFile, usize), uefi::Error> {
+    let mut buf = [0u16; 1024];
+    let Ok(uefi_path) = CStr16::from_str_with_buf(path, &mut buf) else {
+        log::error!("{path} is too long or has characters UEFI can't take");
+        return Err(Status::INVALID_PARAMETER.into());
+    };
+
+    let file = volume.open(uefi_path, FileMode::Read, FileAttribute::empty())?;
+
+    let FileType::Regular(mut file) = file.into_type()? else {
+        log::error!("{path} is a directory, not a file");
+        return Err(Status::INVALID_PARAMETER.into());
+    };
+
+    log::debug!("Found {path}");
+
+    let mut file_info_buf = vec![0; 512];
+    let file_info = file
+        .get_info::<FileInfo>(&mut file_info_buf)
+        .map_err(|error| error.status())?;
+    let length = file_info.file_size() as usize;
+
+    Ok((file, length))
+}
+
+/// Reads a whole file from the volume into a page-aligned buffer. The buffer
+/// is never freed, since whatever is read here (e.g. the kernel image) has to
+/// outlive the loader.
+pub fn read_file(
+    volume: &mut Directory,
+    path: &str,
+) -> Result<Pin<&'static mut [u8]>, uefi::Error> {
+    let (mut file, length) = open_file(volume, path)?;
+    if length == 0 {
+        log::debug!("{path} is empty");
+        return Ok(Pin::static_mut(Default::default()));
+    }
+
+    let Some(mut contents) = page_aligned_buffer(length) else {
+        log::error!("No memory to read {length} bytes of {path}");
+        return Err(Status::OUT_OF_RESOURCES.into());
+    };
+
+    let bytes_read = file
+        .read(&mut contents[..])
+        .map_err(|error| error.status())?;
+    log::debug!("Read {} bytes of {}", bytes_read, path);
+
+    Ok(contents)
+}
+
+/// Allocates a page-aligned buffer that lives as long as the loader's heap.
+/// Returns `None` if `length` is zero or there isn't enough memory.
+pub fn page_aligned_buffer(length: usize) -> Option<Pin<&'static mut [u8]>> {
+    if length == 0 {
+        return None;
+    }
+
+    let layout = Layout::from_size_align(length, Size4KiB::SIZE as usize).ok()?;
+
+    unsafe {
+        let ptr = alloc::alloc::alloc(layout);
+        if ptr.is_null() {
+            return None;
+        }
+
+        let slice = core::slice::from_raw_parts_mut(ptr, length);
+        Some(Pin::static_mut(slice))
+    }
+}
+
+/// Like `read_file`, but a missing file isn't an error.
+pub fn read_optional_file(
+    volume: &mut Directory,
+    path: &str,
+) -> Result<Option<Pin<&'static mut [u8]>>, uefi::Error> {
+    match read_file(volume, path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) if error.status() == Status::NOT_FOUND => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Reads a whole file into pages of the given memory type, so that they show
+/// up as such in the memory map handed to the kernel. Returns the physical
+/// address and size of the file.
+pub fn read_file_to_pages(
+    boot_services: &BootServices,
+    volume: &mut Directory,
+    path: &str,
+    memory_type: MemoryType,
+) -> Result<(PhysAddr, usize), uefi::Error> {
+    let (mut file, length) = open_file(volume, path)?;
+
+    let pages = (length as u64).div_ceil(Size4KiB::SIZE).max(1) as usize;
+    let base_addr = boot_services.allocate_pages(AllocateType::AnyPages, memory_type, pages)?;
+
+    let contents = unsafe { core::slice::from_raw_parts_mut(base_addr as *mut u8, length) };
+    let bytes_read = file.read(contents).map_err(|error| error.status())?;
+    log::debug!("Read {bytes_read} bytes of {path} to {base_addr:#X}");
+
+    Ok((PhysAddr::new(base_addr), length))
+}
@@ -1,7 +1,13 @@
 mod elf;
-use alloc::vec::Vec;
+mod pe;
+
+use core::ops::Range;
+
+use alloc::{collections::BTreeMap, vec::Vec};
 use x86_64::{
-    structures::paging::{FrameAllocator, PageSize, PhysFrame}, VirtAddr,
+    align_down, align_up,
+    structures::paging::{FrameAllocator, PageSize, PhysFrame},
+    VirtAddr,
 };
 
 pub struct FrameMapping<S: PageSize> {
@@ -14,16 +20,196 @@ pub struct FrameMapping<S: PageSize> {
 pub struct LoadResult<S: PageSize> {
     pub entry_point: VirtAddr,
     pub mappings: Vec<FrameMapping<S>>,
+    // how far the image was moved from the address it was linked at
+    pub slide: u64,
+}
+
+/// Virtual address ranges that are already spoken for, and so can't have the
+/// kernel image loaded on top of them.
+pub struct AddressSpace {
+    reserved: Vec<Range<u64>>,
+}
+
+impl AddressSpace {
+    pub fn new() -> Self {
+        Self {
+            reserved: Vec::new(),
+        }
+    }
+
+    pub fn reserve(&mut self, range: Range<u64>) {
+        self.reserved.push(range);
+    }
+
+    pub fn is_free(&self, range: &Range<u64>) -> bool {
+        self.reserved
+            .iter()
+            .all(|reserved| range.end <= reserved.start || range.start >= reserved.end)
+    }
+
+    /// Finds the lowest `align`-aligned address at or above `minimum` where
+    /// `size` bytes are free.
+    pub fn find_free(&self, size: u64, align: u64, minimum: u64) -> Option<u64> {
+        let mut candidate = align_up(minimum, align);
+
+        loop {
+            let range = candidate..candidate.checked_add(size)?;
+
+            match self
+                .reserved
+                .iter()
+                .filter(|reserved| range.end > reserved.start && range.start < reserved.end)
+                .map(|reserved| reserved.end)
+                .max()
+            {
+                None => return Some(candidate),
+                Some(end) => candidate = align_up(end, align),
+            }
+        }
+    }
+}
+
+/// Collects the pages of a kernel image as it's being loaded, allocating a
+/// frame for each page the first time something is placed in it.
+pub struct ImageBuilder<S: PageSize> {
+    mappings: Vec<FrameMapping<S>>,
+
+    // regions may share a page at their boundaries, so remember which
+    // mapping each page went into so the second region reuses its frame
+    mapping_for_page: BTreeMap<u64, usize>,
+}
+
+impl<S: PageSize> ImageBuilder<S> {
+    pub fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+            mapping_for_page: BTreeMap::new(),
+        }
+    }
+
+    /// Maps `mem_size` bytes at `virt_addr`, filled from `data` and zeroed
+    /// beyond the end of it (e.g. for `.bss`).
+    pub fn add_region(
+        &mut self,
+        frame_allocator: &mut impl FrameAllocator<S>,
+        virt_addr: u64,
+        mem_size: u64,
+        data: &[u8],
+        writable: bool,
+        executable: bool,
+    ) {
+        if data.len() as u64 > mem_size {
+            panic!(
+                "Region at {virt_addr:#X} has more data ({data_size}) than memory ({mem_size})",
+                data_size = data.len()
+            );
+        }
+
+        let region_end = virt_addr + mem_size;
+        let data_end = virt_addr + data.len() as u64;
+
+        let first_page = align_down(virt_addr, S::SIZE);
+        let last_page = align_up(region_end, S::SIZE);
+
+        for page_addr in (first_page..last_page).step_by(S::SIZE as usize) {
+            let mappings = &mut self.mappings;
+            let index = *self.mapping_for_page.entry(page_addr).or_insert_with(|| {
+                // allocate a fresh page, zeroed so that anything not covered
+                // by data starts out empty
+                let phys_frame = frame_allocator.allocate_frame().unwrap();
+
+                unsafe {
+                    core::ptr::write_bytes(
+                        phys_frame.start_address().as_u64() as *mut u8,
+                        0,
+                        S::SIZE as usize,
+                    );
+                }
+
+                mappings.push(FrameMapping {
+                    phys_frame,
+                    virt_addr: VirtAddr::new(page_addr),
+                    writable: false,
+                    executable: false,
+                });
+
+                mappings.len() - 1
+            });
+
+            let mapping = &mut self.mappings[index];
+            mapping.writable |= writable;
+            mapping.executable |= executable;
+
+            // copy over whatever part of the data lands in this page
+            let copy_start = page_addr.max(virt_addr);
+            let copy_end = (page_addr + S::SIZE).min(data_end);
+
+            if copy_start < copy_end {
+                let data_offset = (copy_start - virt_addr) as usize;
+                let length = (copy_end - copy_start) as usize;
+
+                unsafe {
+                    let page_slice = core::slice::from_raw_parts_mut(
+                        mapping.phys_frame.start_address().as_u64() as *mut u8,
+                        S::SIZE as usize,
+                    );
+
+                    let page_offset = (copy_start - page_addr) as usize;
+                    page_slice[page_offset..page_offset + length]
+                        .copy_from_slice(&data[data_offset..data_offset + length]);
+                }
+            }
+        }
+    }
+
+    fn byte_ptr(&self, virt_addr: u64) -> *mut u8 {
+        let page_addr = align_down(virt_addr, S::SIZE);
+        let index = match self.mapping_for_page.get(&page_addr) {
+            Some(index) => *index,
+            None => panic!("Address {virt_addr:#X} is outside of the kernel image"),
+        };
+
+        let frame_addr = self.mappings[index].phys_frame.start_address().as_u64();
+        (frame_addr + (virt_addr - page_addr)) as *mut u8
+    }
+
+    /// Reads from the loaded image. Values may straddle a page boundary, so
+    /// this goes byte by byte.
+    pub fn read<const N: usize>(&self, virt_addr: u64) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = unsafe { *self.byte_ptr(virt_addr + i as u64) };
+        }
+        bytes
+    }
+
+    pub fn write<const N: usize>(&mut self, virt_addr: u64, bytes: [u8; N]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            unsafe { *self.byte_ptr(virt_addr + i as u64) = *byte };
+        }
+    }
+
+    pub fn finish(self, entry_point: VirtAddr, slide: u64) -> LoadResult<S> {
+        LoadResult {
+            entry_point,
+            mappings: self.mappings,
+            slide,
+        }
+    }
 }
 
 pub fn load_binary<S: PageSize>(
     file: &[u8],
     object: &goblin::Object,
+    address_space: &AddressSpace,
+    slide: u64,
     frame_allocator: &mut impl FrameAllocator<S>,
-) -> LoadResult<S> {
+) -> Result<LoadResult<S>, uefi::Error> {
     match object {
-        goblin::Object::Elf(binary) => elf::load_elf_binary(file, binary, frame_allocator),
-        goblin::Object::PE(_) => unimplemented!("PE kernel"),
+        goblin::Object::Elf(binary) => elf::load_elf_binary(file, binary, slide, frame_allocator),
+        goblin::Object::PE(binary) => {
+            pe::load_pe_binary(file, binary, address_space, slide, frame_allocator)
+        }
         goblin::Object::Mach(_) => unimplemented!("Mach kernel"),
         goblin::Object::Archive(_) => unimplemented!("Archive kernel"),
         goblin::Object::Unknown(_) => unimplemented!("Unknown kernel"),
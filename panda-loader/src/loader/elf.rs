@@ -1,75 +1,105 @@
-use alloc::vec::Vec;
-use goblin::elf::Elf;
+use goblin::elf::{
+    header::ET_DYN,
+    program_header::PT_LOAD,
+    reloc::{R_X86_64_NONE, R_X86_64_RELATIVE},
+    Elf,
+};
 
+use uefi::Status;
 use x86_64::{
     structures::paging::{FrameAllocator, PageSize},
     VirtAddr,
 };
 
-use crate::loader::{FrameMapping, LoadResult};
+use crate::loader::{ImageBuilder, LoadResult};
 
 pub fn load_elf_binary<S: PageSize, A: FrameAllocator<S>>(
     file: &[u8],
     binary: &Elf,
+    slide: u64,
     frame_allocator: &mut A,
-) -> LoadResult<S> {
-    log::debug!("Entry point at {:#X}", binary.entry);
+) -> Result<LoadResult<S>, uefi::Error> {
+    // only position independent kernels can be moved
+    let slide = if binary.header.e_type == ET_DYN {
+        slide
+    } else {
+        if slide != 0 {
+            log::warn!("Kernel isn't position independent, loading it without KASLR");
+        }
+
+        0
+    };
 
-    let mut mappings = Vec::new();
+    log::debug!("Entry point at {:#X}", binary.entry + slide);
+
+    let mut image = ImageBuilder::new();
+
+    for segment in binary.program_headers.iter() {
+        if segment.p_type != PT_LOAD {
+            continue;
+        }
 
-    for section in binary.section_headers.iter() {
-        let name = binary.shdr_strtab.get_at(section.sh_name).unwrap();
         log::debug!(
-            "Section {name}: addr={addr:#08X}, size={size}",
-            addr = section.sh_addr,
-            size = section.sh_size
+            "Segment: addr={addr:#08X}, file size={file_size}, memory size={mem_size}, flags={r}{w}{x}",
+            addr = segment.p_vaddr + slide,
+            file_size = segment.p_filesz,
+            mem_size = segment.p_memsz,
+            r = if segment.is_read() { "R" } else { "-" },
+            w = if segment.is_write() { "W" } else { "-" },
+            x = if segment.is_executable() { "X" } else { "-" },
         );
 
-        if section.is_alloc() {
-            if section.sh_addralign != S::SIZE {
-                panic!(
-                    "Section {name} has {actual}-byte alignment, expected {expected}",
-                    name = name,
-                    actual = section.sh_addralign,
-                    expected = S::SIZE
-                );
+        if segment.p_memsz == 0 {
+            continue;
+        }
+
+        image.add_region(
+            frame_allocator,
+            segment.p_vaddr + slide,
+            segment.p_memsz,
+            &file[segment.file_range()],
+            segment.is_write(),
+            segment.is_executable(),
+        );
+    }
+
+    if slide != 0 {
+        apply_relocations(&mut image, binary, slide)?;
+    }
+
+    Ok(image.finish(VirtAddr::new(binary.entry + slide), slide))
+}
+
+/// A static PIE kernel only needs its absolute addresses adjusting, which
+/// are all `R_X86_64_RELATIVE` relocations.
+fn apply_relocations<S: PageSize>(
+    image: &mut ImageBuilder<S>,
+    binary: &Elf,
+    slide: u64,
+) -> Result<(), uefi::Error> {
+    let mut count = 0;
+
+    for relocation in binary.dynrelas.iter() {
+        match relocation.r_type {
+            R_X86_64_NONE => {}
+
+            R_X86_64_RELATIVE => {
+                let addend = relocation.r_addend.unwrap_or(0) as u64;
+                let target = relocation.r_offset + slide;
+                image.write(target, addend.wrapping_add(slide).to_le_bytes());
+                count += 1;
             }
 
-            let pages_needed = section.sh_size.div_ceil(S::SIZE);
-            for i in 0..pages_needed {
-                let offset = i * S::SIZE;
-
-                unsafe {
-                    // allocate page-aligned memory
-                    let phys_frame = frame_allocator.allocate_frame().unwrap();
-
-                    let page_slice = core::slice::from_raw_parts_mut(
-                        phys_frame.start_address().as_u64() as *mut u8,
-                        S::SIZE as usize,
-                    );
-
-                    // copy over data from file
-                    let bytes = (section.sh_size - offset).min(S::SIZE);
-                    page_slice[0..bytes as usize].copy_from_slice(
-                        &file[(section.sh_offset + offset) as usize
-                            ..(section.sh_offset + offset + bytes) as usize],
-                    );
-
-                    let virt_addr = VirtAddr::new(section.sh_addr + offset);
-
-                    mappings.push(FrameMapping {
-                        phys_frame,
-                        virt_addr,
-                        writable: section.is_writable(),
-                        executable: section.is_executable(),
-                    });
-                }
+            relocation_type => {
+                log::error!(
+                    "Unsupported relocation type {relocation_type} at {offset:#X}",
+                    offset = relocation.r_offset
+                );
+                return Err(Status::LOAD_ERROR.into());
             }
         }
     }
 
-    LoadResult {
-        entry_point: VirtAddr::new(binary.entry),
-        mappings,
-    }
+    log::debug!("Applied {count} relocations");
+    Ok(())
 }
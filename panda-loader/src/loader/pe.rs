@@ -0,0 +1,192 @@
+use goblin::pe::{
+    section_table::{IMAGE_SCN_CNT_UNINITIALIZED_DATA, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_WRITE},
+    PE,
+};
+use uefi::Status;
+use x86_64::{
+    structures::paging::{FrameAllocator, PageSize, Size2MiB},
+    VirtAddr,
+};
+
+use crate::loader::{AddressSpace, ImageBuilder, LoadResult};
+
+// base relocation types, from the PE/COFF spec
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+pub fn load_pe_binary<S: PageSize, A: FrameAllocator<S>>(
+    file: &[u8],
+    binary: &PE,
+    address_space: &AddressSpace,
+    slide: u64,
+    frame_allocator: &mut A,
+) -> Result<LoadResult<S>, uefi::Error> {
+    if !binary.is_64 {
+        log::error!("Only PE32+ kernel images are supported");
+        return Err(Status::LOAD_ERROR.into());
+    }
+
+    let Some(optional_header) = binary.header.optional_header else {
+        log::error!("PE kernel image has no optional header");
+        return Err(Status::LOAD_ERROR.into());
+    };
+    let windows_fields = optional_header.windows_fields;
+    let relocation_table = *optional_header.data_directories.get_base_relocation_table();
+
+    // only images with base relocations can be moved
+    let slide = if relocation_table.is_some() {
+        slide
+    } else {
+        if slide != 0 {
+            log::warn!("Kernel has no base relocation table, loading it without KASLR");
+        }
+
+        0
+    };
+
+    let preferred_base = binary.image_base as u64;
+    let image_size = windows_fields.size_of_image as u64;
+    let target_base = preferred_base + slide;
+
+    let image_base = if address_space.is_free(&(target_base..target_base + image_size)) {
+        target_base
+    } else if relocation_table.is_none() {
+        log::error!("Base {target_base:#X} is taken, and the kernel can't be relocated");
+        return Err(Status::LOAD_ERROR.into());
+    } else {
+        let Some(base) = address_space.find_free(image_size, Size2MiB::SIZE, target_base) else {
+            log::error!("No free address range for the kernel image");
+            return Err(Status::LOAD_ERROR.into());
+        };
+
+        log::info!("Base {target_base:#X} is taken, relocating kernel to {base:#X}");
+        base
+    };
+
+    log::debug!("Entry point at {:#X}", image_base + binary.entry as u64);
+
+    let mut image = ImageBuilder::new();
+
+    // the headers are part of the image too, and some code expects to be able
+    // to find them at the image base
+    let headers_size = windows_fields.size_of_headers as u64;
+    image.add_region(
+        frame_allocator,
+        image_base,
+        headers_size,
+        &file[..headers_size as usize],
+        false,
+        false,
+    );
+
+    for section in &binary.sections {
+        let name = section.name().unwrap_or("<invalid>");
+        let writable = section.characteristics & IMAGE_SCN_MEM_WRITE != 0;
+        let executable = section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0;
+
+        log::debug!(
+            "Section {name}: addr={addr:#08X}, size={size}, flags={w}{x}",
+            addr = image_base + section.virtual_address as u64,
+            size = section.virtual_size,
+            w = if writable { "W" } else { "-" },
+            x = if executable { "X" } else { "-" },
+        );
+
+        // some linkers leave virtual_size as zero and only fill in the raw size
+        let mem_size = match section.virtual_size {
+            0 => section.size_of_raw_data,
+            size => size,
+        } as u64;
+
+        if mem_size == 0 {
+            continue;
+        }
+
+        let data: &[u8] = if section.characteristics & IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0 {
+            &[]
+        } else {
+            // the raw data is padded to the file alignment, so it can be
+            // larger than the section itself
+            let start = section.pointer_to_raw_data as usize;
+            let length = (section.size_of_raw_data as u64).min(mem_size) as usize;
+            &file[start..start + length]
+        };
+
+        image.add_region(
+            frame_allocator,
+            image_base + section.virtual_address as u64,
+            mem_size,
+            data,
+            writable,
+            executable,
+        );
+    }
+
+    match relocation_table {
+        Some(relocation_table) if image_base != preferred_base => apply_base_relocations(
+            &mut image,
+            image_base,
+            image_base.wrapping_sub(preferred_base),
+            relocation_table.virtual_address as u64,
+            relocation_table.size as u64,
+        )?,
+        _ => {}
+    }
+
+    Ok(image.finish(
+        VirtAddr::new(image_base + binary.entry as u64),
+        image_base.wrapping_sub(preferred_base),
+    ))
+}
+
+/// Walks the `.reloc` blocks in the loaded image and adds `delta` to every
+/// absolute address they point at.
+fn apply_base_relocations<S: PageSize>(
+    image: &mut ImageBuilder<S>,
+    image_base: u64,
+    delta: u64,
+    table_rva: u64,
+    table_size: u64,
+) -> Result<(), uefi::Error> {
+    let mut block_addr = image_base + table_rva;
+    let table_end = block_addr + table_size;
+    let mut count = 0;
+
+    while block_addr < table_end {
+        let page_rva = u32::from_le_bytes(image.read(block_addr)) as u64;
+        let block_size = u32::from_le_bytes(image.read(block_addr + 4)) as u64;
+
+        if block_size < 8 {
+            log::error!("Invalid base relocation block at {block_addr:#X}");
+            return Err(Status::LOAD_ERROR.into());
+        }
+
+        for entry_addr in (block_addr + 8..block_addr + block_size).step_by(2) {
+            let entry = u16::from_le_bytes(image.read(entry_addr));
+            let relocation_type = entry >> 12;
+            let target = image_base + page_rva + (entry & 0xFFF) as u64;
+
+            match relocation_type {
+                IMAGE_REL_BASED_ABSOLUTE => {}
+
+                IMAGE_REL_BASED_DIR64 => {
+                    let value = u64::from_le_bytes(image.read(target));
+                    image.write(target, value.wrapping_add(delta).to_le_bytes());
+                    count += 1;
+                }
+
+                _ => {
+                    log::error!(
+                        "Unsupported base relocation type {relocation_type} at {target:#X}"
+                    );
+                    return Err(Status::LOAD_ERROR.into());
+                }
+            }
+        }
+
+        block_addr += block_size;
+    }
+
+    log::debug!("Applied {count} base relocations");
+    Ok(())
+}
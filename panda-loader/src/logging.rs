@@ -1,6 +1,5 @@
 use core::fmt::Write;
 
-use log::Level;
 use uart_16550::SerialPort;
 use uefi::table::Boot;
 
@@ -17,7 +16,7 @@ macro_rules! print {
 
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
-    if let Some(console) = unsafe { UEFI_CONSOLE } {
+    if let (Some(console), false) = unsafe { (UEFI_CONSOLE, CONSOLE_HIDDEN) } {
         let _ = unsafe { &mut *console }.write_fmt(args);
     } else if let Some(serial_port) = unsafe { &mut QEMU_OUTPUT } {
         let _ = serial_port.write_fmt(args);
@@ -28,6 +27,7 @@ pub struct ConsoleLogger;
 
 static mut QEMU_OUTPUT: Option<SerialPort> = None;
 static mut UEFI_CONSOLE: Option<*mut uefi::proto::console::text::Output> = None;
+static mut CONSOLE_HIDDEN: bool = false;
 
 pub fn init(system_table: &uefi::table::SystemTable<Boot>) -> Result<(), log::SetLoggerError> {
     let mut qemu_output = unsafe { SerialPort::new(0x3F8) };
@@ -52,6 +52,20 @@ pub fn init(system_table: &uefi::table::SystemTable<Boot>) -> Result<(), log::Se
     Ok(())
 }
 
+/// Sends output to the serial port rather than the UEFI console, so that it
+/// doesn't scribble over the boot splash.
+pub fn hide_console() {
+    unsafe {
+        CONSOLE_HIDDEN = true;
+    }
+}
+
+pub fn show_console() {
+    unsafe {
+        CONSOLE_HIDDEN = false;
+    }
+}
+
 pub fn exit_boot_services() {
     unsafe {
         UEFI_CONSOLE = None;
@@ -60,7 +74,7 @@ pub fn exit_boot_services() {
 
 impl log::Log for ConsoleLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &log::Record) {
@@ -10,49 +10,67 @@
 
 extern crate alloc;
 
+mod config;
+mod decompress;
+mod files;
 mod loader;
 mod logging;
+mod menu;
+mod paging;
 mod panic;
-
-use core::{alloc::Layout, pin::Pin};
-
-use alloc::{vec, vec::Vec};
+mod panic_report;
+mod random;
+mod slots;
+mod symbols;
+mod verify;
+
+use alloc::{format, string::String, vec::Vec};
+use config::BootConfig;
+use loader::{AddressSpace, LoadResult};
+use paging::KernelPageTables;
 use panda_loader_lib::{
-    FrameBuffer, KernelEntryFn, LoaderCarePackage, MemoryDescriptor, MemoryDescriptorType,
-    PixelFormat,
+    BootModule, BootSlot, BootStage, EfiMemoryMap, FirmwareTables, FrameBuffer, KernelVerification,
+    LoaderCarePackage, MemoryDescriptor, MemoryDescriptorType, PixelBitmask, PixelFormat,
+    SymbolTable,
 };
 use uefi::{
     prelude::*,
-    proto::{
-        console::gop::{self, GraphicsOutput},
-        media::{
-            file::{File, FileAttribute, FileInfo, FileMode, FileType},
-            fs::SimpleFileSystem,
-        },
-    },
+    proto::console::gop::{self, GraphicsOutput},
     table::{
-        boot::{AllocateType, MemoryType},
-        cfg::ACPI2_GUID,
-        runtime::ResetType,
-        Runtime,
+        boot::{AllocateType, BootServices, MemoryType},
+        cfg::{ACPI2_GUID, ACPI_GUID, SMBIOS3_GUID, SMBIOS_GUID},
     },
-    CStr16,
 };
 use x86_64::{
-    registers::control::Cr3,
-    structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
-        PhysFrame, Size2MiB, Size4KiB,
-    },
+    structures::paging::{FrameAllocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB},
     PhysAddr, VirtAddr,
 };
 
-const PHYSICAL_MEMORY_VIRTUAL_BASE: VirtAddr = unsafe { VirtAddr::new_unsafe(0x000000000) };
+/// The kernel image, its page tables and its stack are allocated as this
+/// type, so that the kernel can tell them apart from the loader's own
+/// allocations in the memory map.
+const KERNEL_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0000);
+
+/// Likewise for boot modules.
+const BOOT_MODULE_MEMORY_TYPE: MemoryType = MemoryType::custom(0x8000_0001);
 
 struct BootResult {
     entry_point: VirtAddr,
     loader_care_package: LoaderCarePackage,
-    system_table: SystemTable<Runtime>,
+    page_tables: KernelPageTables,
+}
+
+/// What's kept from the boot volume once it's been closed, which has to
+/// happen before boot services are exited.
+struct LoadedKernel {
+    command_line: String,
+    load_result: LoadResult<Size4KiB>,
+    modules: Vec<BootModule>,
+    symbols: SymbolTable<'static>,
+    verification: KernelVerification,
+    boot_slot: BootSlot,
+    frame_buffer: FrameBuffer,
+    splash: bool,
 }
 
 fn uefi_boot(handle: Handle, system_table: SystemTable<Boot>) -> Result<BootResult, uefi::Error> {
@@ -70,76 +88,131 @@ fn uefi_boot(handle: Handle, system_table: SystemTable<Boot>) -> Result<BootResu
         log::info!("Booted by UEFI {}.{}!", major, minor);
     }
 
-    let mut frame_allocator = ArenaFrameAllocator::from_uefi(&system_table, 5000)?;
-    let load_result = {
-        let fs = system_table
-            .boot_services()
-            .locate_protocol::<SimpleFileSystem>()?;
-        let fs = unsafe { &mut *fs.get() };
-
-        let mut volume = fs.open_volume().expect("Could not find volume");
-        let mut buf = [0u16; 1024];
-        let kernel_file = volume.open(
-            CStr16::from_str_with_buf("\\EFI\\kernel.elf", &mut buf).unwrap(),
-            FileMode::Read,
-            FileAttribute::empty(),
-        )?;
+    panic_report::show_last_panic(&system_table);
 
-        let mut kernel_file = if let FileType::Regular(kernel_file) = kernel_file.into_type()? {
-            kernel_file
-        } else {
-            panic!("Kernel image is not a file");
+    let mut frame_allocator = ArenaFrameAllocator::from_uefi(&system_table, 5000)?;
+    let loaded_kernel = {
+        let mut volume = files::open_boot_volume(system_table.boot_services(), handle)?;
+
+        let mut config = match files::read_optional_file(&mut volume, config::CONFIG_PATH)? {
+            Some(contents) => match core::str::from_utf8(&contents) {
+                Ok(text) => BootConfig::parse(text),
+                Err(error) => {
+                    log::warn!(
+                        "{} is not UTF-8, using defaults: {error}",
+                        config::CONFIG_PATH
+                    );
+                    BootConfig::default()
+                }
+            },
+            None => {
+                log::info!("No {}, using defaults", config::CONFIG_PATH);
+                BootConfig::default()
+            }
         };
 
-        log::debug!("Found kernel image");
+        log::set_max_level(config.log_level);
+        log::debug!("Boot config: {config:?}");
 
-        let mut kernel_image_info_buf = vec![0; 102];
-        let kernel_image_info = kernel_file
-            .get_info::<FileInfo>(&mut kernel_image_info_buf)
-            .expect("Could not get kernel image file info");
+        slots::select_default(system_table.runtime_services(), &mut config);
+        let entry = &config.entries[menu::choose_entry(&system_table, &config)?];
 
-        let mut kernel_image = unsafe {
-            let length = kernel_image_info.file_size() as usize;
-            let layout = Layout::new::<u8>()
-                .repeat_packed(length)
-                .unwrap()
-                .align_to(Size4KiB::SIZE as usize)
-                .unwrap();
-            let ptr = alloc::alloc::alloc(layout) as *mut u8;
-            let slice = core::slice::from_raw_parts_mut(ptr, length);
+        let boot_slot = slots::slot_of(&config, &entry.name);
+        if boot_slot == BootSlot::A {
+            slots::record_attempt(system_table.runtime_services(), &config)?;
+        }
 
-            Pin::static_mut(slice)
-        };
+        let mut frame_buffer = framebuffer_from_uefi(&system_table, config.video_mode)?;
+        if config.splash {
+            panda_loader_lib::draw_splash(&mut frame_buffer);
+            logging::hide_console();
+        }
 
-        let bytes_read = kernel_file
-            .read(&mut kernel_image[..])
-            .expect("could not read kernel image");
-        log::debug!("Read {} bytes of kernel image", bytes_read);
+        let kernel_image = files::read_file(&mut volume, &entry.kernel_path)?;
+
+        let signature_path = format!("{}.sig", entry.kernel_path);
+        let signature = files::read_optional_file(&mut volume, &signature_path)?;
+        let verification = verify::verify_kernel(&kernel_image, signature.as_deref());
+        enforce_verification(
+            system_table.boot_services(),
+            &entry.kernel_path,
+            verification,
+        )?;
+
+        // the signature covers the file as it is on disk, so a compressed
+        // kernel is only unpacked once it's been checked
+        let kernel_image = decompress::decompress(kernel_image).map_err(|error| {
+            log::error!("Could not decompress {}: {error:?}", entry.kernel_path);
+            Status::LOAD_ERROR
+        })?;
 
         let kernel_object =
             goblin::Object::parse(&kernel_image[..]).expect("Could not parse kernel image");
 
-        let load_result = loader::load_binary(&*kernel_image, &kernel_object, &mut frame_allocator);
+        let address_space = kernel_address_space();
+        let slide = kernel_slide(system_table.boot_services(), config.kaslr);
+        let load_result = loader::load_binary(
+            &*kernel_image,
+            &kernel_object,
+            &address_space,
+            slide,
+            &mut frame_allocator,
+        )?;
 
-        load_result
-    };
+        let symbols = symbols::load_symbol_table(
+            system_table.boot_services(),
+            &kernel_object,
+            KERNEL_MEMORY_TYPE,
+        )?;
 
-    let mut rsdp_address = None;
+        if config.splash {
+            panda_loader_lib::draw_progress(&mut frame_buffer, BootStage::KernelLoaded);
+        }
 
-    for table in system_table.config_table() {
-        match table.guid {
-            ACPI2_GUID => {
-                log::debug!(
-                    "Found ACPI 2.0 RSDP table at {address:X}",
-                    address = table.address as usize
-                );
-                rsdp_address = Some(PhysAddr::new(table.address as u64))
-            }
-            _ => {}
+        let mut modules = Vec::with_capacity(entry.modules.len());
+        for path in &entry.modules {
+            let (base_addr, size) = files::read_file_to_pages(
+                system_table.boot_services(),
+                &mut volume,
+                path,
+                BOOT_MODULE_MEMORY_TYPE,
+            )?;
+
+            let name = path.rsplit('\\').next().unwrap_or(path);
+            log::info!("Loaded module {name} ({size} bytes) at {base_addr:?}");
+
+            modules.push(BootModule::new(name, base_addr, size as u64));
         }
-    }
 
-    let frame_buffer = framebuffer_from_uefi(&system_table)?;
+        if config.splash {
+            panda_loader_lib::draw_progress(&mut frame_buffer, BootStage::ModulesLoaded);
+        }
+
+        LoadedKernel {
+            command_line: entry.command_line.clone(),
+            load_result,
+            modules,
+            symbols,
+            verification,
+            boot_slot,
+            frame_buffer,
+            splash: config.splash,
+        }
+    };
+
+    let LoadedKernel {
+        command_line,
+        load_result,
+        modules,
+        symbols,
+        verification,
+        boot_slot,
+        mut frame_buffer,
+        splash,
+    } = loaded_kernel;
+
+    let mut firmware_tables = firmware_tables_from_uefi(&system_table);
+
     let mmap_size = system_table.boot_services().memory_map_size();
     let mut mmap_buf = Vec::new();
     mmap_buf.resize(mmap_size.map_size * 2, 0);
@@ -147,93 +220,302 @@ fn uefi_boot(handle: Handle, system_table: SystemTable<Boot>) -> Result<BootResu
 
     log::debug!("Exiting boot services...");
 
-    let (system_table, memory_map_iter) = system_table.exit_boot_services(handle, &mut mmap_buf)?;
+    let (_system_table, memory_map_iter) =
+        system_table.exit_boot_services(handle, &mut mmap_buf)?;
 
     // if you get a memory error here, it's probably because something
     // allocated above is being deallocated below, when the allocator has been dropped.
     uefi::alloc::exit_boot_services();
     logging::exit_boot_services();
 
+    if splash {
+        panda_loader_lib::draw_progress(&mut frame_buffer, BootStage::BootServicesExited);
+    }
+
     log::debug!("Boot services exited, copying memory map...");
+    firmware_tables.efi_memory_map = EfiMemoryMap {
+        address: 0,
+        size: memory_map_iter.len() * mmap_size.entry_size,
+        descriptor_size: mmap_size.entry_size,
+        descriptor_version: uefi::table::boot::MemoryDescriptor::VERSION,
+    };
+
     memory_map_iter
+        .inspect(|descriptor| {
+            // the descriptors aren't necessarily at the start of the buffer
+            if firmware_tables.efi_memory_map.address == 0 {
+                firmware_tables.efi_memory_map.address = *descriptor as *const _ as u64;
+            }
+        })
         .map(|descriptor| MemoryDescriptor {
             base_addr: PhysAddr::new(descriptor.phys_start),
             length: descriptor.page_count * Size4KiB::SIZE,
             memory_type: match descriptor.ty {
                 MemoryType::CONVENTIONAL => MemoryDescriptorType::Available,
                 MemoryType::ACPI_RECLAIM => MemoryDescriptorType::AcpiReclaimable,
+                MemoryType::ACPI_NON_VOLATILE => MemoryDescriptorType::AcpiNvs,
+                MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => MemoryDescriptorType::Mmio,
+                MemoryType::RUNTIME_SERVICES_CODE => MemoryDescriptorType::RuntimeServicesCode,
+                MemoryType::RUNTIME_SERVICES_DATA => MemoryDescriptorType::RuntimeServicesData,
+                MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA => {
+                    MemoryDescriptorType::BootServicesReclaimable
+                }
+                KERNEL_MEMORY_TYPE => MemoryDescriptorType::LoaderKernel,
+                BOOT_MODULE_MEMORY_TYPE => MemoryDescriptorType::LoaderModules,
+                MemoryType::PERSISTENT_MEMORY => MemoryDescriptorType::Persistent,
+                MemoryType::UNUSABLE => MemoryDescriptorType::Unusable,
+                // which the care package's arrays live in
+                MemoryType::LOADER_CODE | MemoryType::LOADER_DATA => {
+                    MemoryDescriptorType::LoaderReclaimable
+                }
                 _ => MemoryDescriptorType::Reserved,
             },
         })
         .collect_into(&mut memory_map);
     core::mem::forget(mmap_buf);
 
-    let level_4_table = unsafe {
-        let (cr3, _) = Cr3::read();
-        &mut *(cr3.start_address().as_u64() as *mut PageTable)
-    };
-
-    let mut mapper = unsafe { OffsetPageTable::new(level_4_table, PHYSICAL_MEMORY_VIRTUAL_BASE) };
-
-    for mapping in &load_result.mappings {
-        let mut flags = PageTableFlags::PRESENT;
-
-        if mapping.writable {
-            flags |= PageTableFlags::WRITABLE;
-        }
-
-        // if !mapping.executable {
-        //     flags |= PageTableFlags::NO_EXECUTE;
-        // }
+    // cover at least the first 4 GiB, since that's where most devices are
+    let physical_memory_end = memory_map
+        .iter()
+        .map(|descriptor| descriptor.base_addr.as_u64() + descriptor.length)
+        .chain(core::iter::once(
+            (frame_buffer.base_addr + frame_buffer.stride * frame_buffer.height * 4) as u64,
+        ))
+        .fold(0x1_0000_0000, u64::max);
+    let physical_memory_end = x86_64::align_up(physical_memory_end, Size1GiB::SIZE);
+
+    if physical_memory_end > paging::PHYSICAL_MEMORY_MAX_SIZE {
+        panic!("Physical memory ends at {physical_memory_end:#X}, which is too big to map");
+    }
 
-        unsafe {
-            let page = Page::<Size2MiB>::from_start_address(mapping.virt_addr).unwrap();
+    log::debug!("Mapping physical memory up to {physical_memory_end:#X}");
 
-            mapper.unmap(page).expect("Could not unmap page").1.flush();
-            mapper
-                .map_to(page, mapping.phys_frame, flags, &mut frame_allocator)
-                .unwrap()
-                .flush();
-
-            log::debug!("Mapped {page:?} to {phys:?}", phys = mapping.phys_frame);
-        }
-    }
+    let mut page_tables = KernelPageTables::new(&mut frame_allocator);
+    page_tables.map_physical_memory(physical_memory_end, &mut frame_allocator);
+    page_tables.map_identity(physical_memory_end, &mut frame_allocator);
+    page_tables.map_kernel_image(&load_result, &mut frame_allocator);
+    page_tables.map_kernel_stack(&mut frame_allocator);
 
     let entry_point = load_result.entry_point.clone();
+    let kernel_slide = load_result.slide;
     core::mem::forget(load_result);
 
     let loader_care_package = LoaderCarePackage::new(
         frame_buffer,
         memory_map,
-        PHYSICAL_MEMORY_VIRTUAL_BASE,
-        rsdp_address,
+        paging::PHYSICAL_MEMORY_VIRTUAL_BASE,
+        firmware_tables,
+        command_line,
+        modules,
+        symbols,
+        kernel_slide,
+        verification,
+        boot_slot,
+        splash,
     );
 
     Ok(BootResult {
         entry_point,
         loader_care_package,
-        system_table,
+        page_tables,
     })
 }
 
-fn framebuffer_from_uefi(system_table: &SystemTable<Boot>) -> Result<FrameBuffer, uefi::Error> {
+/// Refuses to boot a kernel that failed verification, unless the loader was
+/// built with the `allow-unsigned-kernel` feature.
+fn enforce_verification(
+    boot_services: &BootServices,
+    kernel_path: &str,
+    verification: KernelVerification,
+) -> Result<(), uefi::Error> {
+    match verification {
+        KernelVerification::Verified => {
+            log::info!("Kernel signature verified");
+            Ok(())
+        }
+
+        _ if cfg!(feature = "allow-unsigned-kernel") => {
+            log::warn!("Kernel failed verification ({verification:?}), booting it anyway");
+            Ok(())
+        }
+
+        _ => {
+            logging::show_console();
+            println!();
+            println!("*** Kernel verification failed: {verification:?} ***");
+            println!("Refusing to boot {kernel_path}. It needs a {kernel_path}.sig signed");
+            println!("with the key this loader was built with (see `make keys`).");
+
+            // give whoever is watching a chance to read that before the
+            // firmware moves on to the next boot option
+            boot_services.stall(10_000_000);
+
+            Err(Status::SECURITY_VIOLATION.into())
+        }
+    }
+}
+
+/// Picks a random 2 MiB aligned amount to move the kernel image by, keeping
+/// it within the top 2 GiB of the address space.
+fn kernel_slide(boot_services: &BootServices, kaslr: bool) -> u64 {
+    const MAX_SLIDE: u64 = 0x4000_0000;
+
+    if !kaslr {
+        log::info!("KASLR is turned off");
+        return 0;
+    }
+
+    let Some(random) = random::random_u64(boot_services) else {
+        log::warn!("No source of randomness, loading the kernel without KASLR");
+        return 0;
+    };
+
+    let slide = (random % (MAX_SLIDE / Size2MiB::SIZE)) * Size2MiB::SIZE;
+    log::debug!("Kernel slide is {slide:#X}");
+    slide
+}
+
+/// The parts of the kernel's address space that a relocatable kernel image
+/// can't be placed in.
+fn kernel_address_space() -> AddressSpace {
+    let direct_map_start = paging::PHYSICAL_MEMORY_VIRTUAL_BASE.as_u64();
+    let stack_start = paging::KERNEL_STACK_TOP.as_u64() - paging::KERNEL_STACK_SIZE;
+
+    let mut address_space = AddressSpace::new();
+    address_space.reserve(0..paging::USER_SPACE_END);
+    address_space.reserve(paging::USER_SPACE_END..paging::KERNEL_SPACE_START);
+    address_space.reserve(direct_map_start..direct_map_start + paging::PHYSICAL_MEMORY_MAX_SIZE);
+    // including the guard page below the stack
+    address_space.reserve(stack_start - Size4KiB::SIZE..paging::KERNEL_STACK_TOP.as_u64());
+    address_space
+}
+
+fn firmware_tables_from_uefi(system_table: &SystemTable<Boot>) -> FirmwareTables {
+    let mut tables = FirmwareTables {
+        // SystemTable is a transparent wrapper around a pointer to the table
+        efi_system_table_address: unsafe {
+            core::mem::transmute_copy::<SystemTable<Boot>, u64>(system_table)
+        },
+        ..FirmwareTables::default()
+    };
+
+    for table in system_table.config_table() {
+        let address = table.address as u64;
+
+        match table.guid {
+            ACPI2_GUID => {
+                log::debug!("Found ACPI 2.0 RSDP at {address:#X}");
+                tables.rsdp_address = address;
+                tables.rsdp_revision = 2;
+            }
+            // only use the ACPI 1.0 RSDP if there's no newer one
+            ACPI_GUID if tables.rsdp_address == 0 => {
+                log::debug!("Found ACPI 1.0 RSDP at {address:#X}");
+                tables.rsdp_address = address;
+                tables.rsdp_revision = 0;
+            }
+            SMBIOS_GUID => {
+                log::debug!("Found SMBIOS entry point at {address:#X}");
+                tables.smbios_address = address;
+            }
+            SMBIOS3_GUID => {
+                log::debug!("Found SMBIOS 3 entry point at {address:#X}");
+                tables.smbios3_address = address;
+            }
+            _ => {}
+        }
+    }
+
+    if tables.rsdp_address == 0 {
+        log::warn!("Firmware didn't provide an ACPI RSDP");
+    }
+
+    tables
+}
+
+fn framebuffer_from_uefi(
+    system_table: &SystemTable<Boot>,
+    video_mode: Option<(usize, usize)>,
+) -> Result<FrameBuffer, uefi::Error> {
     let gop = system_table
         .boot_services()
         .locate_protocol::<GraphicsOutput>()?;
     let gop = unsafe { &mut *gop.get() };
-    let current_mode_info = gop.current_mode_info().clone();
+
+    // modes without a linear framebuffer are no use to the kernel
+    let modes = gop
+        .modes()
+        .filter(|mode| mode.info().pixel_format() != gop::PixelFormat::BltOnly)
+        .collect::<Vec<_>>();
+
+    for mode in &modes {
+        let info = mode.info();
+        log::debug!(
+            "Video mode {width}x{height} ({format:?})",
+            width = info.resolution().0,
+            height = info.resolution().1,
+            format = info.pixel_format()
+        );
+    }
+
+    let requested_mode = video_mode.and_then(|resolution| {
+        let mode = modes
+            .iter()
+            .find(|mode| mode.info().resolution() == resolution);
+
+        if mode.is_none() {
+            log::warn!("Video mode {resolution:?} is not available");
+        }
+
+        mode
+    });
+
+    let mode = requested_mode.or_else(|| {
+        modes.iter().max_by_key(|mode| {
+            let (width, height) = mode.info().resolution();
+            width * height
+        })
+    });
+
+    match mode {
+        Some(mode) => {
+            let (width, height) = mode.info().resolution();
+            log::info!("Switching to {width}x{height} video mode");
+            gop.set_mode(mode)?;
+        }
+        None => log::warn!("No usable video modes, keeping the current one"),
+    }
+
+    let current_mode_info = gop.current_mode_info();
+    let (width, height) = current_mode_info.resolution();
+
+    let pixel_format = match current_mode_info.pixel_format() {
+        gop::PixelFormat::Rgb => PixelFormat::RGB,
+        gop::PixelFormat::Bgr => PixelFormat::BGR,
+        gop::PixelFormat::Bitmask => {
+            let bitmask = current_mode_info.pixel_bitmask().unwrap();
+
+            PixelFormat::Bitmask(PixelBitmask {
+                red: bitmask.red,
+                green: bitmask.green,
+                blue: bitmask.blue,
+                reserved: bitmask.reserved,
+            })
+        }
+        gop::PixelFormat::BltOnly => {
+            log::error!("Video mode {width}x{height} has no frame buffer");
+            return Err(Status::UNSUPPORTED.into());
+        }
+    };
+
     let mut frame_buffer = gop.frame_buffer();
 
     Ok(FrameBuffer {
         base_addr: frame_buffer.as_mut_ptr() as usize,
-        resolution: current_mode_info.resolution(),
+        width,
+        height,
         stride: current_mode_info.stride(),
-        pixel_format: match current_mode_info.pixel_format() {
-            gop::PixelFormat::Rgb => PixelFormat::RGB,
-            gop::PixelFormat::Bgr => PixelFormat::BGR,
-            gop::PixelFormat::Bitmask => todo!(),
-            gop::PixelFormat::BltOnly => todo!(),
-        },
+        pixel_format,
     })
 }
 
@@ -242,21 +524,20 @@ fn uefi_start(handle: Handle, system_table: SystemTable<Boot>) -> Status {
     match uefi_boot(handle, system_table) {
         Ok(BootResult {
             entry_point,
-            ref mut loader_care_package,
-            system_table,
+            loader_care_package,
+            page_tables,
         }) => {
-            let start = unsafe { core::mem::transmute::<u64, KernelEntryFn>(entry_point.as_u64()) };
+            // the care package is on our stack, which the kernel can only
+            // reach through the direct map
+            let care_package = &loader_care_package as *const LoaderCarePackage;
+            let care_package = (paging::PHYSICAL_MEMORY_VIRTUAL_BASE + care_package as u64)
+                .as_ptr::<LoaderCarePackage>();
 
             log::info!("Starting kernel...");
-            start(loader_care_package);
-            log::warn!("Kernel returned, shutting down machine");
-            unsafe {
-                system_table
-                    .runtime_services()
-                    .reset(ResetType::Shutdown, Status::SUCCESS, None);
-            }
+            unsafe { page_tables.enter_kernel(entry_point, care_package) }
         }
         Err(error) => {
+            logging::show_console();
             println!("UEFI boot failed: {:?}", error);
             error.status()
         }
@@ -279,7 +560,7 @@ impl ArenaFrameAllocator {
     fn from_uefi(system_table: &SystemTable<Boot>, pages: usize) -> Result<Self, uefi::Error> {
         let start_addr = system_table.boot_services().allocate_pages(
             AllocateType::AnyPages,
-            MemoryType::LOADER_DATA,
+            KERNEL_MEMORY_TYPE,
             pages,
         )?;
 
@@ -0,0 +1,132 @@
+use core::fmt::Write;
+
+use uefi::{
+    proto::console::text::{Color, Key, Output, ScanCode},
+    table::{Boot, SystemTable},
+};
+
+use crate::config::BootConfig;
+
+/// How long to wait between checks for a key press, in microseconds.
+const POLL_INTERVAL: usize = 50_000;
+
+/// Lets the user pick an entry from a menu on the text console, using the
+/// arrow keys and Enter. The default entry is booted if the timeout runs
+/// out first; pressing any key stops the countdown. Returns the index of the
+/// chosen entry.
+pub fn choose_entry(
+    system_table: &SystemTable<Boot>,
+    config: &BootConfig,
+) -> Result<usize, uefi::Error> {
+    let default = config.default_entry_index();
+    let mut selected = default;
+
+    if config.entries.len() < 2 || config.timeout == Some(0) {
+        return Ok(selected);
+    }
+
+    let stdin = system_table.stdin();
+    let stdout = system_table.stdout();
+
+    // don't let keys pressed before the menu appeared pick something, though
+    // it's no reason not to boot if the console can't
+    let _ = stdin.reset(false);
+
+    let mut remaining = config
+        .timeout
+        .map(|seconds| (seconds as usize).saturating_mul(1_000_000));
+
+    draw(stdout, config, selected, default, remaining)?;
+
+    loop {
+        let key = match stdin.read_key() {
+            Ok(key) => key,
+            Err(error) => {
+                // with no way to choose, carry on as if the timeout ran out
+                log::warn!("Could not read the keyboard, booting the default entry: {error:?}");
+                selected = default;
+                break;
+            }
+        };
+
+        let Some(key) = key else {
+            if let Some(time) = remaining {
+                if time == 0 {
+                    break;
+                }
+
+                system_table.boot_services().stall(POLL_INTERVAL);
+                let time = time.saturating_sub(POLL_INTERVAL);
+                remaining = Some(time);
+
+                // only redraw when the number of seconds shown changes
+                if time % 1_000_000 == 0 {
+                    draw(stdout, config, selected, default, remaining)?;
+                }
+            } else {
+                system_table.boot_services().stall(POLL_INTERVAL);
+            }
+
+            continue;
+        };
+
+        remaining = None;
+
+        match key {
+            Key::Special(ScanCode::UP) => selected = selected.saturating_sub(1),
+            Key::Special(ScanCode::DOWN) => selected = (selected + 1).min(config.entries.len() - 1),
+            Key::Special(ScanCode::HOME) => selected = 0,
+            Key::Special(ScanCode::END) => selected = config.entries.len() - 1,
+            Key::Printable(c) if char::from(c) == '\r' => break,
+            _ => {}
+        }
+
+        draw(stdout, config, selected, default, remaining)?;
+    }
+
+    stdout.set_color(Color::LightGray, Color::Black)?;
+    stdout.clear()?;
+
+    log::info!("Booting entry {:?}", config.entries[selected].name);
+    Ok(selected)
+}
+
+fn draw(
+    stdout: &mut Output,
+    config: &BootConfig,
+    selected: usize,
+    default: usize,
+    remaining: Option<usize>,
+) -> Result<(), uefi::Error> {
+    stdout.set_color(Color::LightGray, Color::Black)?;
+    stdout.clear()?;
+    // serial and graphics consoles often can't hide the cursor
+    let _ = stdout.enable_cursor(false);
+
+    let _ = writeln!(stdout, "Panda boot menu\n");
+
+    for (index, entry) in config.entries.iter().enumerate() {
+        if index == selected {
+            stdout.set_color(Color::Black, Color::LightGray)?;
+        } else {
+            stdout.set_color(Color::LightGray, Color::Black)?;
+        }
+
+        let _ = write!(stdout, "  {}  ", entry.title());
+        stdout.set_color(Color::LightGray, Color::Black)?;
+        let _ = writeln!(stdout);
+    }
+
+    let _ = writeln!(
+        stdout,
+        "\nUse the arrow keys to choose an entry and Enter to boot it."
+    );
+
+    if let Some(time) = remaining {
+        let seconds = time.div_ceil(1_000_000);
+        let default = config.entries[default].title();
+        let _ = writeln!(stdout, "Booting {default} in {seconds}s...");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use core::arch::asm;
+
+use panda_loader_lib::LoaderCarePackage;
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
+        PhysFrame, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use crate::loader::LoadResult;
+
+/// Everything below this address is left for user space.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Where the upper, kernel half of the address space starts. The addresses
+/// between here and `USER_SPACE_END` aren't canonical, so nothing can live
+/// there.
+pub const KERNEL_SPACE_START: u64 = 0xFFFF_8000_0000_0000;
+
+/// All of physical memory is mapped starting here.
+pub const PHYSICAL_MEMORY_VIRTUAL_BASE: VirtAddr =
+    unsafe { VirtAddr::new_unsafe(0xFFFF_8000_0000_0000) };
+
+/// The most physical memory the direct map has room for (64 TiB).
+pub const PHYSICAL_MEMORY_MAX_SIZE: u64 = 0x4000_0000_0000;
+
+/// The kernel's stack sits just below the kernel image, with an unmapped
+/// guard page underneath it.
+pub const KERNEL_STACK_TOP: VirtAddr = unsafe { VirtAddr::new_unsafe(0xFFFF_FFFF_8000_0000) };
+pub const KERNEL_STACK_SIZE: u64 = 128 * 1024;
+
+/// The page tables the kernel starts with. They're built from scratch rather
+/// than on top of the firmware's, so the kernel knows exactly what's mapped.
+pub struct KernelPageTables {
+    level_4_frame: PhysFrame,
+    mapper: OffsetPageTable<'static>,
+}
+
+impl KernelPageTables {
+    pub fn new<A: FrameAllocator<Size4KiB>>(frame_allocator: &mut A) -> Self {
+        let level_4_frame = frame_allocator
+            .allocate_frame()
+            .expect("Could not allocate a frame for the page tables");
+
+        // the firmware identity maps everything, so until we switch to the
+        // new tables a physical address can be used as is
+        let level_4_table = unsafe {
+            let table = &mut *(level_4_frame.start_address().as_u64() as *mut PageTable);
+            table.zero();
+            table
+        };
+
+        let mapper = unsafe { OffsetPageTable::new(level_4_table, VirtAddr::new(0)) };
+
+        Self {
+            level_4_frame,
+            mapper,
+        }
+    }
+
+    /// Maps physical memory up to `end` at `PHYSICAL_MEMORY_VIRTUAL_BASE`,
+    /// using 2 MiB pages.
+    pub fn map_physical_memory<A: FrameAllocator<Size4KiB>>(
+        &mut self,
+        end: u64,
+        frame_allocator: &mut A,
+    ) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        self.map_range(PHYSICAL_MEMORY_VIRTUAL_BASE, end, flags, frame_allocator);
+    }
+
+    /// Maps physical memory up to `end` at the same virtual addresses. The
+    /// loader is running from there when it switches to these page tables,
+    /// so this has to be executable; the kernel removes it once it's up.
+    pub fn map_identity<A: FrameAllocator<Size4KiB>>(&mut self, end: u64, frame_allocator: &mut A) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        self.map_range(VirtAddr::new(0), end, flags, frame_allocator);
+    }
+
+    fn map_range<A: FrameAllocator<Size4KiB>>(
+        &mut self,
+        virt_base: VirtAddr,
+        end: u64,
+        flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) {
+        for phys_addr in (0..end).step_by(Size2MiB::SIZE as usize) {
+            let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(phys_addr));
+            let page = Page::<Size2MiB>::containing_address(virt_base + phys_addr);
+
+            unsafe {
+                self.mapper
+                    .map_to(page, frame, flags, frame_allocator)
+                    .expect("Could not map physical memory")
+                    .ignore();
+            }
+        }
+    }
+
+    pub fn map_kernel_image<A: FrameAllocator<Size4KiB>>(
+        &mut self,
+        load_result: &LoadResult<Size4KiB>,
+        frame_allocator: &mut A,
+    ) {
+        for mapping in &load_result.mappings {
+            if mapping.writable && mapping.executable {
+                panic!(
+                    "Kernel page at {addr:?} is both writable and executable",
+                    addr = mapping.virt_addr
+                );
+            }
+
+            let mut flags = PageTableFlags::PRESENT;
+
+            if mapping.writable {
+                flags |= PageTableFlags::WRITABLE;
+            }
+
+            if !mapping.executable {
+                flags |= PageTableFlags::NO_EXECUTE;
+            }
+
+            let page = Page::<Size4KiB>::from_start_address(mapping.virt_addr).unwrap();
+
+            unsafe {
+                self.mapper
+                    .map_to(page, mapping.phys_frame, flags, frame_allocator)
+                    .expect("Could not map kernel page")
+                    .ignore();
+            }
+
+            log::debug!(
+                "Mapped {page:?} to {phys:?} ({flags:?})",
+                phys = mapping.phys_frame
+            );
+        }
+    }
+
+    /// Maps a fresh stack below `KERNEL_STACK_TOP`.
+    pub fn map_kernel_stack<A: FrameAllocator<Size4KiB>>(&mut self, frame_allocator: &mut A) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let stack_bottom = KERNEL_STACK_TOP - KERNEL_STACK_SIZE;
+
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(stack_bottom),
+            Page::containing_address(KERNEL_STACK_TOP),
+        );
+
+        for page in pages {
+            let frame = frame_allocator
+                .allocate_frame()
+                .expect("Could not allocate a frame for the kernel stack");
+
+            unsafe {
+                self.mapper
+                    .map_to(page, frame, flags, frame_allocator)
+                    .expect("Could not map kernel stack")
+                    .ignore();
+            }
+        }
+    }
+
+    /// Switches to these page tables and the kernel stack, and jumps to the
+    /// kernel. `care_package` must be a pointer that's valid in the new
+    /// address space.
+    pub unsafe fn enter_kernel(
+        self,
+        entry_point: VirtAddr,
+        care_package: *const LoaderCarePackage,
+    ) -> ! {
+        // the NO_EXECUTE bit is reserved (and faults) unless this is turned on
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+
+        // make read-only pages read-only for the kernel too, so that stray
+        // writes into code or constants fault instead of going unnoticed
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+
+        // the kernel entry point uses the win64 calling convention, so the
+        // argument goes in rcx and the caller leaves 32 bytes of shadow space
+        asm!(
+            "mov cr3, {level_4_table}",
+            "mov rsp, {stack_top}",
+            "xor rbp, rbp",
+            "sub rsp, 32",
+            "call {entry_point}",
+            "2:",
+            "cli",
+            "hlt",
+            "jmp 2b",
+            level_4_table = in(reg) self.level_4_frame.start_address().as_u64(),
+            stack_top = in(reg) KERNEL_STACK_TOP.as_u64(),
+            entry_point = in(reg) entry_point.as_u64(),
+            in("rcx") care_package,
+            options(noreturn)
+        )
+    }
+}
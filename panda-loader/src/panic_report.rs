@@ -0,0 +1,81 @@
+use alloc::{string::String, vec};
+use panda_loader_lib::{PANIC_REPORT_ATTRIBUTES, PANIC_REPORT_MAX_SIZE, PANIC_REPORT_VARIABLE};
+use uefi::{
+    table::{runtime::VariableAttributes, Boot, SystemTable},
+    CStr16, Status,
+};
+
+use crate::slots;
+
+/// How long to leave the report on screen, in microseconds, unless a key is
+/// pressed first.
+const DISPLAY_TIME: usize = 30_000_000;
+
+/// How long to wait between checks for a key press, in microseconds.
+const POLL_INTERVAL: usize = 50_000;
+
+/// Shows the report the kernel saved the last time it panicked, if there is
+/// one, deleting it first so that it's only shown once. Nothing here is
+/// worth failing the boot over.
+pub fn show_last_panic(system_table: &SystemTable<Boot>) {
+    let runtime_services = system_table.runtime_services();
+
+    let mut name_buf = [0u16; 32];
+    let name = CStr16::from_str_with_buf(PANIC_REPORT_VARIABLE, &mut name_buf).unwrap();
+
+    let mut report = vec![0u8; PANIC_REPORT_MAX_SIZE];
+    let size = match runtime_services.get_variable(name, &slots::vendor(), &mut report) {
+        Ok((size, _)) => size,
+        Err(error) if error.status() == Status::NOT_FOUND => return,
+        Err(error) => {
+            log::warn!(
+                "Could not read {PANIC_REPORT_VARIABLE}: {:?}",
+                error.status()
+            );
+            return;
+        }
+    };
+
+    // the report starts wherever the kernel's log tail did, which could be
+    // halfway through a character
+    let report = String::from_utf8_lossy(&report[..size]);
+
+    // writing nothing deletes the variable. It's done before waiting, so the
+    // report doesn't stick around if something goes wrong from here on
+    let deleted = runtime_services.set_variable(
+        name,
+        &slots::vendor(),
+        VariableAttributes::from_bits_truncate(PANIC_REPORT_ATTRIBUTES),
+        &[],
+    );
+
+    if let Err(error) = deleted {
+        log::warn!(
+            "Could not delete {PANIC_REPORT_VARIABLE}: {:?}",
+            error.status()
+        );
+    }
+
+    println!();
+    println!("*** The kernel panicked the last time it booted ***");
+    println!();
+    println!("{report}");
+    println!();
+    println!("Press any key to continue.");
+
+    // a console without working input (say on a headless machine) ends the
+    // wait early rather than failing the boot
+    let stdin = system_table.stdin();
+    let _ = stdin.reset(false);
+
+    let mut remaining = DISPLAY_TIME;
+    while remaining > 0 {
+        match stdin.read_key() {
+            Ok(None) => {}
+            Ok(Some(_)) | Err(_) => break,
+        }
+
+        system_table.boot_services().stall(POLL_INTERVAL);
+        remaining = remaining.saturating_sub(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,83 @@
+use core::arch::x86_64::{__cpuid, _rdseed64_step};
+
+use uefi::{prelude::*, proto::Protocol, table::boot::BootServices, unsafe_guid, Guid};
+use x86_64::instructions::random::RdRand;
+
+/// `EFI_RNG_PROTOCOL`, which the uefi crate doesn't wrap.
+#[repr(C)]
+#[unsafe_guid("3152bca5-eade-433d-862e-c01cdc291f44")]
+#[derive(Protocol)]
+struct Rng {
+    #[allow(dead_code)]
+    get_info: unsafe extern "efiapi" fn(
+        this: &Rng,
+        algorithm_list_size: *mut usize,
+        algorithm_list: *mut Guid,
+    ) -> Status,
+    get_rng: unsafe extern "efiapi" fn(
+        this: &Rng,
+        algorithm: *const Guid,
+        value_length: usize,
+        value: *mut u8,
+    ) -> Status,
+}
+
+/// A random number from the best source available: the CPU's RDSEED or
+/// RDRAND instructions, or failing those the firmware's RNG protocol.
+pub fn random_u64(boot_services: &BootServices) -> Option<u64> {
+    if let Some(value) = rdseed() {
+        return Some(value);
+    }
+
+    if let Some(value) = RdRand::new().and_then(RdRand::get_u64) {
+        return Some(value);
+    }
+
+    efi_rng(boot_services)
+}
+
+fn rdseed() -> Option<u64> {
+    const RDSEED_SUPPORTED: u32 = 1 << 18;
+
+    // leaf 7 isn't there on older CPUs, and asking for it anyway gets the
+    // highest leaf that is, which says nothing about RDSEED
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf < 7 {
+        return None;
+    }
+
+    let features = unsafe { __cpuid(7) };
+    if features.ebx & RDSEED_SUPPORTED == 0 {
+        return None;
+    }
+
+    // RDSEED can run dry for a moment, so give it a few tries
+    for _ in 0..16 {
+        let mut value = 0;
+        if unsafe { rdseed_step(&mut value) } == 1 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed_step(value: &mut u64) -> i32 {
+    _rdseed64_step(value)
+}
+
+fn efi_rng(boot_services: &BootServices) -> Option<u64> {
+    let rng = boot_services.locate_protocol::<Rng>().ok()?;
+    let rng = unsafe { &*rng.get() };
+
+    let mut value = [0u8; 8];
+    let status = unsafe { (rng.get_rng)(rng, core::ptr::null(), value.len(), value.as_mut_ptr()) };
+
+    if status.is_success() {
+        Some(u64::from_ne_bytes(value))
+    } else {
+        log::warn!("EFI RNG protocol failed: {status:?}");
+        None
+    }
+}
@@ -0,0 +1,86 @@
+use panda_loader_lib::{
+    BootSlot, BOOT_ATTEMPTS_ATTRIBUTES, BOOT_ATTEMPTS_VARIABLE, PANDA_VARIABLE_VENDOR,
+};
+use uefi::{
+    table::runtime::{RuntimeServices, VariableAttributes, VariableVendor},
+    CStr16, Guid, Status,
+};
+
+use crate::config::BootConfig;
+
+/// The vendor all of Panda's UEFI variables are stored under.
+pub fn vendor() -> VariableVendor {
+    // Guid is laid out exactly like an EFI_GUID
+    VariableVendor(unsafe { core::mem::transmute::<[u8; 16], Guid>(PANDA_VARIABLE_VENDOR) })
+}
+
+/// Makes slot A the default entry, or slot B if slot A has used up its boot
+/// attempts. Does nothing unless both slots are configured.
+pub fn select_default(runtime_services: &RuntimeServices, config: &mut BootConfig) {
+    let (Some(slot_a), Some(slot_b)) = (&config.slot_a, &config.slot_b) else {
+        return;
+    };
+
+    let attempts = read_attempts(runtime_services);
+
+    let slot = if attempts < config.boot_attempts {
+        slot_a
+    } else {
+        log::warn!(
+            "Slot A ({slot_a}) failed to boot {attempts} times, falling back to slot B ({slot_b})"
+        );
+        slot_b
+    };
+
+    config.default_entry = Some(slot.clone());
+}
+
+/// Which slot the entry with the given name is in.
+pub fn slot_of(config: &BootConfig, entry_name: &str) -> BootSlot {
+    match (&config.slot_a, &config.slot_b) {
+        (Some(slot_a), Some(_)) if slot_a == entry_name => BootSlot::A,
+        (Some(_), Some(slot_b)) if slot_b == entry_name => BootSlot::B,
+        _ => BootSlot::None,
+    }
+}
+
+/// Counts an attempt at booting slot A. The kernel resets the count once
+/// it's come up properly.
+pub fn record_attempt(
+    runtime_services: &RuntimeServices,
+    config: &BootConfig,
+) -> Result<(), uefi::Error> {
+    let attempts = read_attempts(runtime_services).saturating_add(1);
+    log::info!(
+        "Booting slot A, attempt {attempts} of {}",
+        config.boot_attempts
+    );
+
+    let mut name_buf = [0u16; 32];
+    let name = CStr16::from_str_with_buf(BOOT_ATTEMPTS_VARIABLE, &mut name_buf).unwrap();
+
+    runtime_services.set_variable(
+        name,
+        &vendor(),
+        VariableAttributes::from_bits_truncate(BOOT_ATTEMPTS_ATTRIBUTES),
+        &[attempts],
+    )
+}
+
+fn read_attempts(runtime_services: &RuntimeServices) -> u8 {
+    let mut name_buf = [0u16; 32];
+    let name = CStr16::from_str_with_buf(BOOT_ATTEMPTS_VARIABLE, &mut name_buf).unwrap();
+
+    let mut value = [0u8; 1];
+    match runtime_services.get_variable(name, &vendor(), &mut value) {
+        Ok(_) => value[0],
+        Err(error) if error.status() == Status::NOT_FOUND => 0,
+        Err(error) => {
+            log::warn!(
+                "Could not read {BOOT_ATTEMPTS_VARIABLE}: {:?}",
+                error.status()
+            );
+            0
+        }
+    }
+}
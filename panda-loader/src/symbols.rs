@@ -0,0 +1,75 @@
+use core::mem::size_of;
+
+use alloc::{format, string::String, vec::Vec};
+use goblin::Object;
+use panda_loader_lib::{Symbol, SymbolTable};
+use uefi::table::boot::{AllocateType, BootServices, MemoryType};
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+/// Builds a table of the kernel's function symbols for backtraces, and puts
+/// it in pages of the given memory type so the kernel can keep using it.
+/// Names are demangled here so the kernel doesn't have to.
+pub fn load_symbol_table(
+    boot_services: &BootServices,
+    object: &Object,
+    memory_type: MemoryType,
+) -> Result<SymbolTable<'static>, uefi::Error> {
+    let mut functions: Vec<(u64, u64, String)> = match object {
+        Object::Elf(elf) => elf
+            .syms
+            .iter()
+            .filter(|symbol| symbol.is_function() && symbol.st_value != 0)
+            .filter_map(|symbol| {
+                let name = elf.strtab.get_at(symbol.st_name)?;
+                let name = format!("{:#}", rustc_demangle::demangle(name));
+                Some((symbol.st_value, symbol.st_size, name))
+            })
+            .collect(),
+
+        _ => Vec::new(),
+    };
+
+    if functions.is_empty() {
+        log::warn!("Kernel image has no symbols, backtraces won't have names");
+        return Ok(SymbolTable::empty());
+    }
+
+    functions.sort_unstable_by_key(|(address, _, _)| *address);
+    functions.dedup_by_key(|(address, _, _)| *address);
+
+    let names_size: usize = functions.iter().map(|(_, _, name)| name.len()).sum();
+    let symbols_size = functions.len() * size_of::<Symbol>();
+    let pages = (symbols_size + names_size).div_ceil(Size4KiB::SIZE as usize);
+
+    let base_addr = boot_services.allocate_pages(AllocateType::AnyPages, memory_type, pages)?;
+
+    let (symbols, names) = unsafe {
+        let symbols = core::slice::from_raw_parts_mut(base_addr as *mut Symbol, functions.len());
+        let names = core::slice::from_raw_parts_mut(
+            (base_addr as usize + symbols_size) as *mut u8,
+            names_size,
+        );
+        (symbols, names)
+    };
+
+    let mut name_offset = 0;
+    for ((address, size, name), symbol) in functions.iter().zip(symbols.iter_mut()) {
+        names[name_offset..name_offset + name.len()].copy_from_slice(name.as_bytes());
+
+        *symbol = Symbol {
+            address: *address,
+            size: *size,
+            name_offset: name_offset as u32,
+            name_len: name.len() as u32,
+        };
+
+        name_offset += name.len();
+    }
+
+    log::debug!(
+        "Loaded {count} kernel symbols ({pages} pages) at {base_addr:#X}",
+        count = symbols.len()
+    );
+
+    Ok(SymbolTable::new(symbols, names))
+}
@@ -0,0 +1,29 @@
+use ed25519_compact::{PublicKey, Signature};
+use panda_loader_lib::KernelVerification;
+
+/// The key kernel images are signed with, generated by `make keys`.
+const PUBLIC_KEY: &[u8; PublicKey::BYTES] = include_bytes!("../keys/kernel-signing.pub");
+
+/// Checks a detached Ed25519 signature over the whole kernel image.
+pub fn verify_kernel(image: &[u8], signature: Option<&[u8]>) -> KernelVerification {
+    let Some(signature) = signature else {
+        return KernelVerification::Unsigned;
+    };
+
+    let Ok(signature) = Signature::from_slice(signature) else {
+        log::error!(
+            "Kernel signature should be {} bytes, but it's {}",
+            Signature::BYTES,
+            signature.len()
+        );
+        return KernelVerification::InvalidSignature;
+    };
+
+    match PublicKey::new(*PUBLIC_KEY).verify(image, &signature) {
+        Ok(()) => KernelVerification::Verified,
+        Err(error) => {
+            log::error!("Kernel signature doesn't match: {error:?}");
+            KernelVerification::InvalidSignature
+        }
+    }
+}